@@ -0,0 +1,75 @@
+//! `#[derive(Trace)]` for scgc
+//!
+//! Tracing a struct or an enum traces each of its fields,
+//! so every field type has to implement `Trace` too.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+#[macro_use]
+extern crate syn;
+
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use syn::{Data, DeriveInput, Fields};
+
+
+#[proc_macro_derive(Trace)]
+pub fn derive_trace(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+
+    // a generic container is only traceable with traceable parameters
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::scgc::Trace));
+    }
+
+    let name = &input.ident;
+    let arms = match input.data {
+        Data::Struct(ref data) => vec![arm(quote!(#name), &data.fields)],
+        Data::Enum(ref data) => {
+            data.variants.iter()
+                .map(|variant| {
+                    let variant_name = &variant.ident;
+                    arm(quote!(#name::#variant_name), &variant.fields)
+                })
+                .collect()
+        }
+        Data::Union(_) => {
+            return syn::Error::new_spanned(name, "Trace can't be derived for unions, the active field is unknown")
+                .to_compile_error()
+                .into();
+        }
+    };
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let expanded = quote! {
+        unsafe impl #impl_generics ::scgc::Trace for #name #ty_generics #where_clause {
+            fn trace(&self, tracer: &mut ::scgc::Tracer) {
+                match *self {
+                    #(#arms)*
+                }
+            }
+        }
+    };
+    expanded.into()
+}
+
+/// match arm binding every field of `path` by reference and tracing it
+fn arm(path: TokenStream2, fields: &Fields) -> TokenStream2 {
+    let bindings: Vec<_> = (0..fields.len()).map(|index| format_ident!("field{}", index)).collect();
+    let pattern = match *fields {
+        Fields::Named(ref fields) => {
+            let names = fields.named.iter().map(|field| &field.ident);
+            quote!(#path { #(#names: ref #bindings),* })
+        }
+        Fields::Unnamed(_) => quote!(#path(#(ref #bindings),*)),
+        Fields::Unit => quote!(#path),
+    };
+    quote! {
+        #pattern => {
+            #(::scgc::Trace::trace(#bindings, tracer);)*
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Allocation cost of the example1 workload, as is and scaled up to bigger heaps
+//!
+//! `example1` is example1's loop: `malloc!` of a `[u8; 1024]` replacing one of
+//! two live buffers. `random` replaces a fixed set of live objects at random,
+//! so the heap gets fragmented into holes.
+//!
+//! Run with `cargo run --release --example bench`.
+//!
+//! On one machine, median of three runs in ns per allocation:
+//!
+//! | heap   | example1 | random |
+//! |--------|----------|--------|
+//! | 1 MiB  |       60 |    181 |
+//! | 2 MiB  |       73 |    175 |
+//! | 4 MiB  |       83 |    176 |
+//! | 8 MiB  |       97 |    182 |
+//! | 16 MiB |      120 |    178 |
+//! | 32 MiB |      249 |    195 |
+//!
+//! `random` stays about flat as the heap (and so the number of records) grows,
+//! the free lists don't walk the record table. `example1` grows with the heap,
+//! likely because the buffers it zeroes while bumping through stop fitting in the caches.
+//!
+//! There are no numbers from before the free lists: that tree can't run this
+//! harness. Its `malloc!` re-pinned the stack end, so `example1` loses its live
+//! buffer, and `random` runs out of memory at every heap size.
+
+#[macro_use]
+extern crate scgc;
+
+use scgc::Gc;
+
+use std::ptr;
+use std::time::Instant;
+
+
+const ALLOCATIONS: usize = 200_000;
+const LIVE: usize = 64;
+
+type Workload = fn(&mut Gc);
+
+fn example1(mut gc: &mut Gc) {
+    // the live buffers are the roots, the last slot only marks the end of the range
+    let mut live = [ptr::null::<u8>(); 3];
+    gc.stack_begin(&live[0]);
+    gc.stack_end(&live[2]);
+
+    let data1: &mut [u8; 1024] = unsafe { malloc!(gc, 1024) };
+    data1[0] = 42;
+    live[0] = data1.as_ptr();
+    for i in 0..ALLOCATIONS {
+        let data: &mut [u8; 1024] = unsafe { malloc!(gc) };
+        data[0] = i as u8;
+        live[1] = data.as_ptr();
+    }
+    assert_eq!(data1[0], 42);
+}
+
+fn random(gc: &mut Gc) {
+    // the live objects are the roots, the last slot only marks the end of the range
+    let mut live = [ptr::null::<u8>(); LIVE + 1];
+    gc.stack_begin(&live[0]);
+    gc.stack_end(&live[LIVE]);
+
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    for i in 0..ALLOCATIONS {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        // mostly small objects with an occasional example1 sized buffer
+        let size = if i % 16 == 0 { 1024 } else { 16 + (seed % 240) as usize };
+        live[(seed >> 32) as usize % LIVE] = gc.malloc(size).expect("GC heap exhausted");
+    }
+}
+
+fn main() {
+    let workloads: [(&str, Workload); 2] = [("example1", example1), ("random", random)];
+    for &(name, workload) in &workloads {
+        for shift in 20..26 {
+            let size = 1 << shift;
+            let mut gc = Gc::new(size);
+
+            let start = Instant::now();
+            workload(&mut gc);
+            let elapsed = start.elapsed();
+            println!("{:>8}, heap {:>8} bytes: {:>8.1} ns per allocation",
+                     name,
+                     size,
+                     elapsed.as_secs_f64() * 1e9 / ALLOCATIONS as f64);
+        }
+    }
+}
@@ -13,13 +13,13 @@ fn main() {
     let x = true;
     gc.stack_begin(&x);
 
-    let data1: &mut [u8; 1024] = malloc!(gc, 4096);
+    let data1: &mut [u8; 1024] = unsafe { malloc!(gc, 1024) };
     data1[0] = 42;
     debug!("pointer on stack {:p} -> data on heap {:p}", &data1, data1);
     let mut data2: &mut [u8; 1024] = &mut [0; 1024];
 
     for i in 1..100 {
-        let mut data: &mut [u8; 1024] = malloc!(gc, 4096);
+        let data: &mut [u8; 1024] = unsafe { malloc!(gc) };
         data[0] = i;
         assert_eq!(data2[0], i-1);   // test the cleanup won't accidentally reuse data2
         data2 = data;
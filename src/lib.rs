@@ -3,28 +3,382 @@
 //! As conservative GC doesn't have precise pointer information,
 //! we can't support "moving" feature.
 
-#![feature(alloc, heap_api, oom)]
 #![no_std]
 
 
 extern crate alloc;
+#[cfg(all(feature = "linux", target_os = "linux"))]
+extern crate libc;
 #[macro_use]
 extern crate log;
 
 
+use alloc::alloc::{alloc, dealloc, handle_alloc_error, Layout};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+use core::arch::asm;
+use core::cmp;
+use core::fmt;
+use core::hint;
+use core::marker::PhantomData;
 use core::mem;
+use core::ptr;
+
+
+/// alignment of the backing region, records live at its end so they need it too
+const HEAP_ALIGN: usize = 8;
+
+/// one free list per power of two size class
+const BIN_COUNT: usize = mem::size_of::<usize>() * 8;
+
+/// how many backing chunks a growing heap can have
+const MAX_CHUNKS: usize = 16;
+
+/// extra root ranges which can be registered
+const MAX_ROOTS: usize = 32;
+
+/// callee-saved registers which can hold pointers, spilled to the stack before marking
+#[cfg(all(target_arch = "x86_64", not(target_os = "windows")))]
+const SPILLED_REGISTERS: usize = 6;
+/// the Windows x64 calling convention saves `rsi` and `rdi` too
+#[cfg(all(target_arch = "x86_64", target_os = "windows"))]
+const SPILLED_REGISTERS: usize = 8;
+#[cfg(target_arch = "aarch64")]
+const SPILLED_REGISTERS: usize = 11;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const SPILLED_REGISTERS: usize = 0;
+
+/// touched records waiting to be scanned, more than that falls back to rescanning the record table
+const MARK_STACK_SIZE: usize = 512;
+
+/// stray pointers into free memory remembered at once, more than that are ignored
+const BLACKLIST_SIZE: usize = 64;
 
 
 #[derive(Debug)]
 #[repr(C)]
 pub struct Gc {
-    heap_begin: *const u8,
-    heap_free: *const u8,
-    heap_end: *const u8,
-    heap_size: usize,
+    /// backing regions, the first one is set up by `new` and the rest come from growing
+    chunks: [Chunk; MAX_CHUNKS],
+    chunk_count: usize,
+    growth: Option<GrowthPolicy>,
+    /// look for pointers at every byte instead of every aligned word
+    scan_unaligned: bool,
+    /// keep mark bits in a bitmap per chunk instead of in `Record::status`
+    side_marks: bool,
+    /// which addresses inside an object keep it alive
+    pointer_policy: PointerPolicy,
+    /// free addresses scanning saw a stray pointer into, allocation stays away from them
+    blacklist: Blacklist,
     stack_begin: Option<*const u8>,
     stack_end: Option<*const u8>,
-    record_count: usize,
+    /// ranges scanned along with the stack
+    roots: [RootRange; MAX_ROOTS],
+    /// bumped on every registration, so a stale `RootId` can't remove a newer range
+    root_generation: usize,
+    /// sentinel of the circular list of `Root` handles, on the heap so `Gc` can move
+    root_handles: *mut RootNode,
+    /// scan the writable segments of every loaded object, globals included
+    #[cfg(all(feature = "linux", target_os = "linux"))]
+    scan_data_segments: bool,
+    /// touched records waiting to be scanned in the mark phase
+    mark_stack: [*mut Record; MARK_STACK_SIZE],
+    mark_top: usize,
+    mark_overflow: bool,
+    /// heads of the free lists, bin `k` holds deallocated blocks of `2^k ..= 2^(k+1)-1` bytes
+    bins: [*const u8; BIN_COUNT],
+}
+
+/// When and how much the heap grows after a cleanup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GrowthPolicy {
+    /// bytes of each extra chunk, a request bigger than that gets a chunk of its own size
+    pub chunk_size: usize,
+    /// grow when less than this percentage of the heap is free after cleanup
+    pub min_free_percent: usize,
+}
+
+/// Which addresses inside an object count as a reference to it while marking
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerPolicy {
+    /// only the address of the first byte
+    BaseOnly,
+    /// offsets `0..n` from the start, e.g. for pointers with tag bits
+    Window(usize),
+    /// any address inside the object
+    Interior,
+}
+
+/// Addresses in free memory which looked like pointers during the mark phase
+///
+/// A block holding one of them would be kept alive by that stray value as soon as it's
+/// handed out, so allocation skips them until `cycles` cleanups pass without seeing them again.
+#[derive(Debug)]
+struct Blacklist {
+    /// address and cleanups left
+    entries: [(*const u8, usize); BLACKLIST_SIZE],
+    len: usize,
+    /// 0 turns blacklisting off
+    cycles: usize,
+}
+
+/// Handle to a root range registered by `Gc::add_roots`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RootId {
+    slot: usize,
+    generation: usize,
+}
+
+/// One slot of the root table, `generation` 0 marks a free slot
+#[derive(Debug, Clone, Copy)]
+struct RootRange {
+    begin: *const u8,
+    end: *const u8,
+    generation: usize,
+}
+
+const EMPTY_ROOT_RANGE: RootRange = RootRange {
+    begin: ptr::null(),
+    end: ptr::null(),
+    generation: 0,
+};
+
+/// Pointer to a `T` living in GC's heap
+///
+/// It's a plain pointer in memory, so holding it on the stack (or inside
+/// another GC object) is what keeps the object alive.
+/// It doesn't borrow the `Gc`, reading through it is unsafe, see `as_ref`.
+/// The GC never runs `T`'s destructor.
+#[repr(transparent)]
+pub struct GcPtr<T> {
+    ptr: *mut T,
+    _marker: PhantomData<T>,
+}
+
+impl<T> GcPtr<T> {
+    /// raw pointer to the object
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr
+    }
+
+    /// reference to the object, its lifetime is picked by the caller like `NonNull::as_ref`
+    ///
+    /// # Safety
+    ///
+    /// The object must stay allocated while the reference is used: found by every cleanup
+    /// since it was allocated, and its `Gc` not dropped yet.
+    pub unsafe fn as_ref<'a>(&self) -> &'a T {
+        &*self.ptr
+    }
+}
+
+impl<T> Clone for GcPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for GcPtr<T> {}
+
+impl<T> fmt::Debug for GcPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GcPtr({:p})", self.ptr)
+    }
+}
+
+impl<T> fmt::Pointer for GcPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Pointer::fmt(&self.ptr, f)
+    }
+}
+
+/// Types which can point out every GC pointer they hold
+///
+/// Objects allocated by `Gc::alloc_traced` are scanned by calling `trace`
+/// instead of looking at every word. `#[derive(Trace)]` from the `scgc-derive`
+/// crate implements it by tracing each field.
+///
+/// # Safety
+///
+/// `trace` has to pass on every GC pointer, an object reachable only through
+/// a pointer it misses gets deallocated while still in use.
+pub unsafe trait Trace {
+    /// pass every GC pointer held by `self` to `tracer`
+    fn trace(&self, tracer: &mut Tracer);
+}
+
+/// Collects the pointers a `Trace` impl points out during the mark phase
+pub struct Tracer<'a> {
+    gc: &'a mut Gc,
+}
+
+impl<'a> Tracer<'a> {
+    /// keep the object `ptr` points to alive
+    pub fn mark<T>(&mut self, ptr: GcPtr<T>) {
+        self.gc.mark_object(ptr.ptr as *const u8);
+    }
+}
+
+unsafe impl<T> Trace for GcPtr<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark(*self);
+    }
+}
+
+unsafe impl<T: Trace> Trace for Option<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(ref value) = *self {
+            value.trace(tracer);
+        }
+    }
+}
+
+unsafe impl<T: Trace, const N: usize> Trace for [T; N] {
+    fn trace(&self, tracer: &mut Tracer) {
+        for value in self {
+            value.trace(tracer);
+        }
+    }
+}
+
+unsafe impl<T: Trace> Trace for Vec<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for value in self {
+            value.trace(tracer);
+        }
+    }
+}
+
+unsafe impl<T: Trace> Trace for Box<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        (**self).trace(tracer);
+    }
+}
+
+unsafe impl<T> Trace for PhantomData<T> {
+    fn trace(&self, _: &mut Tracer) {}
+}
+
+/// types without any GC pointer, tracing them does nothing
+macro_rules! trace_nothing {
+    ($($ty:ty),*) => {
+        $(
+            unsafe impl Trace for $ty {
+                fn trace(&self, _: &mut Tracer) {}
+            }
+        )*
+    };
+}
+
+trace_nothing!(bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, (), String);
+
+/// call `T`'s `trace` on the object at `addr`
+unsafe fn trace_erased<T: Trace>(addr: *const u8, tracer: &mut Tracer) {
+    (*(addr as *const T)).trace(tracer);
+}
+
+/// Handle which keeps an object alive whatever the conservative scan finds
+///
+/// It's registered in a list `Gc` walks in the mark phase,
+/// so it can live anywhere, e.g. in a `Vec` outside of GC's heap.
+/// Dropping it unregisters the object. A handle may outlive its `Gc` and still be
+/// dropped, but the object is gone with the `Gc`, `get` only yields a dangling `GcPtr` then.
+pub struct Root<T> {
+    node: *mut RootNode,
+    _marker: PhantomData<T>,
+}
+
+/// One entry of the circular list of `Root` handles
+#[derive(Debug)]
+struct RootNode {
+    addr: *const u8,
+    prev: *mut RootNode,
+    next: *mut RootNode,
+}
+
+impl<T> Root<T> {
+    /// the rooted object, reading it through `GcPtr::as_ref` needs the `Gc` alive
+    pub fn get(&self) -> GcPtr<T> {
+        GcPtr { ptr: unsafe { (*self.node).addr } as *mut T, _marker: PhantomData }
+    }
+}
+
+impl<T> Clone for Root<T> {
+    fn clone(&self) -> Self {
+        Root { node: unsafe { RootNode::insert_after(self.node, (*self.node).addr) }, _marker: PhantomData }
+    }
+}
+
+impl<T> Drop for Root<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let node = Box::from_raw(self.node);
+            (*node.prev).next = node.next;
+            (*node.next).prev = node.prev;
+        }
+    }
+}
+
+impl<T> fmt::Debug for Root<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Root({:p})", self.get().ptr)
+    }
+}
+
+impl RootNode {
+    /// an empty list, the sentinel links to itself
+    fn sentinel() -> *mut RootNode {
+        let node = Box::into_raw(Box::new(RootNode {
+            addr: ptr::null(),
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+        }));
+        unsafe {
+            (*node).prev = node;
+            (*node).next = node;
+        }
+        node
+    }
+
+    /// link a new node for `addr` right after `prev`
+    unsafe fn insert_after(prev: *mut RootNode, addr: *const u8) -> *mut RootNode {
+        let node = Box::into_raw(Box::new(RootNode { addr, prev, next: (*prev).next }));
+        (*(*prev).next).prev = node;
+        (*prev).next = node;
+        node
+    }
+}
+
+/// Reasons a GC heap can't be set up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GcError {
+    /// the global allocator couldn't provide the backing region
+    AllocFailed,
+    /// the heap can't even hold a single `Record`
+    TooSmall,
+    /// the heap size isn't a multiple of the heap alignment
+    Misaligned,
+    /// the heap size is beyond what a single allocation can have
+    TooBig,
+    /// the bounds of the current thread's stack couldn't be found
+    StackUnknown,
+    /// there is no room left for another root range
+    TooManyRoots,
+}
+
+impl fmt::Display for GcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GcError::AllocFailed => write!(f, "failed to allocate GC heap"),
+            GcError::TooSmall => write!(f, "GC heap is too small to hold any record"),
+            GcError::Misaligned => write!(f, "GC heap size is not a multiple of {}", HEAP_ALIGN),
+            GcError::TooBig => write!(f, "GC heap size is more than {} bytes", isize::MAX),
+            GcError::StackUnknown => write!(f, "failed to find the bounds of the current thread's stack"),
+            GcError::TooManyRoots => write!(f, "can't register more than {} root ranges", MAX_ROOTS),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -33,8 +387,81 @@ struct Record {
     addr: *const u8,
     size: usize,
     status: RecordStatus,
+    kind: RecordKind,
+}
+
+/// How the mark phase looks for pointers inside an object
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+enum RecordKind {
+    /// every word may be a pointer
+    Conservative,
+    /// no pointers at all, the object is marked but never scanned
+    Atomic,
+    /// only the words the descriptor picks are pointers
+    Typed(Descriptor),
+    /// the type enumerates its own pointers, this is its `Trace` impl behind a type erased pointer
+    Traced(TraceFn),
 }
 
+/// one entry vtable of a traced object, calls `Trace::trace` on the object at the address
+type TraceFn = unsafe fn(*const u8, &mut Tracer);
+
+/// Which words of an object allocated by `Gc::malloc_typed` hold pointers
+///
+/// It describes a pattern of up to 32 pointer sized words,
+/// objects longer than that repeat it, e.g. arrays of a small struct.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Descriptor {
+    bitmap: u32,
+    words: u32,
+}
+
+impl Descriptor {
+    /// A pattern of `words` words, bit `i` of `bitmap` is set if word `i` holds a pointer
+    ///
+    /// Panic if `words` is 0 or more than 32.
+    pub fn new(bitmap: u32, words: usize) -> Descriptor {
+        assert!(words > 0 && words <= 32, "descriptor covers 1 to 32 words");
+        Descriptor { bitmap, words: words as u32 }
+    }
+
+    /// whether the `index`-th word of the object holds a pointer
+    fn is_pointer(&self, index: usize) -> bool {
+        self.bitmap & (1 << (index % self.words as usize)) != 0
+    }
+}
+
+/// header written at the start of a deallocated block while it sits in a free list
+#[repr(C)]
+struct FreeBlock {
+    next: *const u8,
+    size: usize,
+}
+
+/// One backing region, objects grow upward from `begin`
+/// and the record table grows downward from `end`
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Chunk {
+    begin: *const u8,
+    free: *const u8,
+    end: *const u8,
+    size: usize,
+    record_count: usize,
+    /// side mark bitmap, null unless side marks are on
+    marks: *mut u8,
+}
+
+const EMPTY_CHUNK: Chunk = Chunk {
+    begin: ptr::null(),
+    free: ptr::null(),
+    end: ptr::null(),
+    size: 0,
+    record_count: 0,
+    marks: ptr::null_mut(),
+};
+
 #[derive(Debug, PartialEq)]
 #[repr(C)]
 enum RecordStatus {
@@ -46,22 +473,110 @@ enum RecordStatus {
 
 
 impl Gc {
+    /// Set up a GC with `size` bytes heap, abort the process if the memory isn't available
     pub fn new(size: usize) -> Gc {
+        match Gc::try_new(size) {
+            Ok(gc) => gc,
+            Err(GcError::AllocFailed) => {
+                handle_alloc_error(Layout::from_size_align(size, HEAP_ALIGN).unwrap())
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Set up a GC with `size` bytes heap, report failure instead of aborting
+    pub fn try_new(size: usize) -> Result<Gc, GcError> {
         info!("Setting up GC with {} bytes memory", size);
-        let raw = unsafe { alloc::heap::allocate(size, 8) };
-        if raw.is_null() {
-            alloc::oom();
-        }
-        info!("Available memory address {:p} ~ {:p}", raw, unsafe { raw.offset(size as isize) });
-        Gc {
-            heap_begin: raw,
-            heap_free: raw,
-            heap_end: unsafe { raw.offset(size as isize) },
-            heap_size: size,
+        let first = Chunk::allocate(size)?;
+        let mut gc = Gc {
+            chunks: [EMPTY_CHUNK; MAX_CHUNKS],
+            chunk_count: 1,
+            growth: None,
+            scan_unaligned: false,
+            side_marks: false,
+            pointer_policy: PointerPolicy::Interior,
+            blacklist: Blacklist {
+                entries: [(ptr::null(), 0); BLACKLIST_SIZE],
+                len: 0,
+                cycles: 0,
+            },
             stack_begin: None,
             stack_end: None,
-            record_count: 0,
+            roots: [EMPTY_ROOT_RANGE; MAX_ROOTS],
+            root_generation: 0,
+            root_handles: RootNode::sentinel(),
+            #[cfg(all(feature = "linux", target_os = "linux"))]
+            scan_data_segments: false,
+            mark_stack: [ptr::null_mut(); MARK_STACK_SIZE],
+            mark_top: 0,
+            mark_overflow: false,
+            bins: [ptr::null(); BIN_COUNT],
+        };
+        gc.chunks[0] = first;
+        Ok(gc)
+    }
+
+    /// Let the heap grow by extra chunks, `None` keeps it at the initial size
+    pub fn set_growth_policy(&mut self, policy: Option<GrowthPolicy>) -> &Self {
+        self.growth = policy;
+        info!("Setting growth policy to {:?}", self.growth);
+        self
+    }
+
+    /// Look for pointers at every byte address when scanning, for packed data
+    ///
+    /// It's off by default, only aligned words are scanned.
+    pub fn set_unaligned_scan(&mut self, unaligned: bool) -> &Self {
+        self.scan_unaligned = unaligned;
+        info!("Setting unaligned scan to {}", unaligned);
+        self
+    }
+
+    /// Keep mark bits in a side bitmap instead of the record table
+    ///
+    /// Resetting the marks is then a bulk clear of the bitmap, and `Record` only
+    /// holds layout info. It costs one bit per record slot a chunk could hold.
+    pub fn set_side_marks(&mut self, enabled: bool) -> Result<&Self, GcError> {
+        info!("Setting side marks to {}", enabled);
+        if enabled {
+            for index in 0..self.chunk_count {
+                if let Err(e) = self.chunks[index].allocate_marks() {
+                    for chunk in &mut self.chunks[..index] {
+                        chunk.release_marks();
+                    }
+                    return Err(e);
+                }
+            }
+        } else {
+            for chunk in &mut self.chunks[..self.chunk_count] {
+                chunk.release_marks();
+            }
+        }
+        self.side_marks = enabled;
+        Ok(self)
+    }
+
+    /// Choose which addresses inside an object keep it alive
+    ///
+    /// It's `PointerPolicy::Interior` by default, narrowing it reduces false retention
+    /// but objects only reachable through interior pointers get collected.
+    pub fn set_pointer_policy(&mut self, policy: PointerPolicy) -> &Self {
+        self.pointer_policy = policy;
+        info!("Setting pointer policy to {:?}", policy);
+        self
+    }
+
+    /// Keep allocation away from free addresses a stray pointer was seen into
+    ///
+    /// An address stays blacklisted until `cycles` cleanups pass without seeing it again,
+    /// 0 turns blacklisting off, which is the default.
+    pub fn set_blacklist_cycles(&mut self, cycles: usize) -> &Self {
+        self.blacklist.cycles = cycles;
+        if cycles == 0 {
+            self.blacklist.len = 0;
         }
+        info!("Setting blacklist cycles to {}", cycles);
+        self
     }
 
     pub fn stack_begin<T>(&mut self, addr: &T) -> &Self {
@@ -70,185 +585,1105 @@ impl Gc {
         self
     }
 
+    /// Set the stack begin to the bottom of the current thread's stack
+    ///
+    /// Every frame of the thread is covered then, not only the ones below `main`.
+    #[cfg(all(feature = "linux", target_os = "linux"))]
+    pub fn stack_begin_auto(&mut self) -> Result<&Self, GcError> {
+        let mut attr = mem::MaybeUninit::<libc::pthread_attr_t>::uninit();
+        let mut addr: *mut libc::c_void = ptr::null_mut();
+        let mut size: libc::size_t = 0;
+        unsafe {
+            if libc::pthread_getattr_np(libc::pthread_self(), attr.as_mut_ptr()) != 0 {
+                return Err(GcError::StackUnknown);
+            }
+            let found = libc::pthread_attr_getstack(attr.as_ptr(), &mut addr, &mut size);
+            libc::pthread_attr_destroy(attr.as_mut_ptr());
+            if found != 0 || addr.is_null() {
+                return Err(GcError::StackUnknown);
+            }
+        }
+        // the stack grows downward, its bottom is the highest address
+        self.stack_begin = Some(unsafe { (addr as *const u8).add(size) });
+        info!("Setting Stack begin to {:?}", self.stack_begin);
+        Ok(self)
+    }
+
+    /// Scan `begin..end` for pointers along with the stack on every cleanup
+    ///
+    /// For GC pointers stored outside the stack and GC's heap, e.g. in memory from
+    /// another allocator. The range has to stay readable until it's removed
+    /// with `remove_roots` or the `Gc` is dropped.
+    pub fn add_roots(&mut self, begin: *const u8, end: *const u8) -> Result<RootId, GcError> {
+        let slot = self.roots.iter()
+            .position(|range| range.generation == 0)
+            .ok_or(GcError::TooManyRoots)?;
+        self.root_generation += 1;
+        self.roots[slot] = RootRange {
+            begin: cmp::min(begin, end),
+            end: cmp::max(begin, end),
+            generation: self.root_generation,
+        };
+        info!("Adding roots {:p} ~ {:p} to slot {}", begin, end, slot);
+        Ok(RootId { slot, generation: self.root_generation })
+    }
+
+    /// Stop scanning a range registered by `add_roots`, false if it's already removed
+    pub fn remove_roots(&mut self, id: RootId) -> bool {
+        if self.roots[id.slot].generation != id.generation {
+            return false;
+        }
+        info!("Removing roots {:p} ~ {:p}", self.roots[id.slot].begin, self.roots[id.slot].end);
+        self.roots[id.slot] = EMPTY_ROOT_RANGE;
+        true
+    }
+
+    /// Scan the writable segments of the executable and every loaded library
+    ///
+    /// Globals holding GC pointers keep their objects alive then.
+    /// It's off by default, the segments are looked up again on each cleanup.
+    #[cfg(all(feature = "linux", target_os = "linux"))]
+    pub fn set_scan_data_segments(&mut self, scan: bool) -> &Self {
+        self.scan_data_segments = scan;
+        info!("Setting data segments scan to {}", scan);
+        self
+    }
+
+    /// Keep the object `ptr` points to alive until the returned handle is dropped
+    pub fn root<T>(&mut self, ptr: GcPtr<T>) -> Root<T> {
+        info!("Rooting {:p}", ptr.ptr);
+        Root { node: unsafe { RootNode::insert_after(self.root_handles, ptr.ptr as *const u8) }, _marker: PhantomData }
+    }
+
+    /// Pin the top end of the root range
+    ///
+    /// Without it `cleanup` takes the current stack pointer as the top,
+    /// so it's only needed for scanning a fixed range, `clear_stack_end` undoes it.
     pub fn stack_end<T>(&mut self, addr: &T) -> &Self {
         self.stack_end = Some(addr as *const T as *const u8);
         info!("Setting Stack end to {:?}", self.stack_end);
         self
     }
 
+    /// Go back to taking the current stack pointer as the top of the root range
+    pub fn clear_stack_end(&mut self) -> &Self {
+        self.stack_end = None;
+        info!("Clearing Stack end");
+        self
+    }
+
+    fn chunks(&self) -> &[Chunk] {
+        &self.chunks[..self.chunk_count]
+    }
+
     /// GC cleanup
     pub fn cleanup(&mut self) {
         info!("Start cleanup");
         self.inner_cleanup();
+
+        if let Some(policy) = self.growth {
+            let (free, total) = self.chunks().iter()
+                .fold((0, 0), |(free, total), chunk| (free + chunk.free_bytes(), total + chunk.size));
+            if free * 100 < total * policy.min_free_percent {
+                info!("Only {} of {} bytes free after cleanup", free, total);
+                self.grow(policy.chunk_size);
+            }
+        }
         info!("End cleanup");
     }
 
     fn inner_cleanup(&mut self) {
         // Initial
-        info!("Initialize Record status");
-        let record_size = mem::size_of::<Record>();
-        for index in 1..self.record_count+1 {
-            let raw = unsafe { self.heap_end.offset(-((index*record_size) as isize)) };
-            let mut record = unsafe { mem::transmute::<_, &mut Record>(raw) };
-            if record.status != RecordStatus::Deallocated {
-                record.status = RecordStatus::Unknown;
+        if self.side_marks {
+            info!("Clear the mark bitmaps");
+            for chunk in self.chunks() {
+                chunk.clear_marks();
+            }
+        } else {
+            info!("Initialize Record status");
+            for chunk in self.chunks() {
+                for index in 1..chunk.record_count+1 {
+                    let record = unsafe { &mut *chunk.record_ptr(index) };
+                    if record.status != RecordStatus::Deallocated {
+                        record.status = RecordStatus::Unknown;
+                    }
+                }
             }
         }
 
+        self.blacklist.age();
+
         // Mark
         info!("Start the Mark Phase");
-        self.scan_touch(self.stack_begin.unwrap(), self.stack_end.unwrap());
+        let stack_begin = self.stack_begin.expect("stack begin is not set");
+        let stack_end = self.stack_end.unwrap_or_else(stack_pointer);
+        // the bottom of the stack is above its top when it grows downward
+        self.scan_touch(cmp::min(stack_begin, stack_end), cmp::max(stack_begin, stack_end));
+        // pointers only held in registers aren't on the stack yet
+        let registers = spill_registers();
+        let registers = registers.as_ptr_range();
+        self.scan_touch(registers.start as *const u8, registers.end as *const u8);
+        for index in 0..MAX_ROOTS {
+            let range = self.roots[index];
+            if range.generation != 0 {
+                self.scan_touch(range.begin, range.end);
+            }
+        }
+        let mut node = unsafe { (*self.root_handles).next };
+        while node != self.root_handles {
+            self.mark_object(unsafe { (*node).addr });
+            node = unsafe { (*node).next };
+        }
+        #[cfg(all(feature = "linux", target_os = "linux"))]
+        {
+            if self.scan_data_segments {
+                info!("Scan the data segments");
+                unsafe { libc::dl_iterate_phdr(Some(scan_data_segment), self as *mut Gc as *mut libc::c_void) };
+            }
+        }
 
-        let mut has_touched_record = true;
-        while has_touched_record {
-            has_touched_record = false;
-            for record in
-                (1..self.record_count+1)
-                    .map(|i| unsafe { self.heap_end.offset(-((i*record_size) as isize)) })
-                    .map(|raw| unsafe { mem::transmute::<_, &mut Record>(raw) })
-                    .filter(|r| r.status == RecordStatus::Touched) {
-                record.status = RecordStatus::Referred;
-                has_touched_record = true;
-                self.scan_touch(record.addr, unsafe { record.addr.offset(record.size as isize) });
+        loop {
+            self.drain_mark_stack();
+            if !self.mark_overflow {
+                break;
+            }
+            // touched records which didn't fit are still in the table
+            info!("Mark stack overflowed, rescan the record table");
+            self.mark_overflow = false;
+            for chunk in 0..self.chunk_count {
+                let chunk = self.chunks[chunk];
+                for index in 1..chunk.record_count+1 {
+                    let record = chunk.record_ptr(index);
+                    if self.side_marks {
+                        // the bitmap can't tell scanned from pending, scan every marked record again
+                        if chunk.is_marked(record) {
+                            self.scan_record(record);
+                            self.drain_mark_stack();
+                        }
+                    } else if unsafe { &*record }.status == RecordStatus::Touched {
+                        self.mark_push(record);
+                    }
+                }
             }
         }
 
         // Sweep
         info!("Start the Sweep Phase");
-        for record in
-            (1..self.record_count+1)
-                .map(|i| unsafe { self.heap_end.offset(-((i*record_size) as isize)) })
-                .map(|raw| unsafe { mem::transmute::<_, &mut Record>(raw) })
-                .filter(|r| r.status == RecordStatus::Unknown) {
-            self.free_record(record);
+        for chunk in self.chunks() {
+            for record in
+                (1..chunk.record_count+1)
+                    .map(|i| unsafe { &mut *chunk.record_ptr(i) })
+                    .filter(|r| !self.is_live(chunk, r)) {
+                self.free_record(record);
+            }
+        }
+
+        // Compact
+        info!("Compact the record table");
+        for chunk in &mut self.chunks[..self.chunk_count] {
+            chunk.compact_records();
+        }
+
+        info!("Rebuild the free lists");
+        self.rebuild_bins();
+    }
+
+    /// map one more chunk of at least `size` bytes, false if it isn't possible
+    fn grow(&mut self, size: usize) -> bool {
+        if self.chunk_count == MAX_CHUNKS {
+            info!("Can't grow, already have {} chunks", MAX_CHUNKS);
+            return false;
+        }
+        let size = cmp::max(size, mem::size_of::<Record>());
+        let size = match size.checked_add(HEAP_ALIGN - 1) {
+            Some(size) => size & !(HEAP_ALIGN - 1),
+            None => {
+                info!("Can't grow, {} bytes is too big", size);
+                return false;
+            }
+        };
+        match Chunk::allocate(size) {
+            Ok(mut chunk) => {
+                if self.side_marks {
+                    if let Err(e) = chunk.allocate_marks() {
+                        info!("Can't grow: {}", e);
+                        unsafe { dealloc(chunk.begin as *mut u8, chunk.layout()) };
+                        return false;
+                    }
+                }
+                self.chunks[self.chunk_count] = chunk;
+                self.chunk_count += 1;
+                true
+            }
+            Err(e) => {
+                info!("Can't grow: {}", e);
+                false
+            }
         }
     }
 
-    /// allocate raw memory under GC's contronl
+    /// allocate raw memory under GC's control, aligned for pointer sized data
     pub fn malloc(&mut self, size: usize) -> Option<*const u8> {
-        info!("Try to allocate memory");
-        let record_size = mem::size_of::<Record>();
+        self.malloc_aligned(size, mem::align_of::<usize>())
+    }
 
-        // from free memory
-        if (size + record_size) <=
-            (self.heap_end as usize -
-             self.heap_free as usize -
-             record_size * self.record_count) {
-
-            let result = self.heap_free;
-            self.heap_free = unsafe { self.heap_free.offset(size as isize) };
-            self.record_count += 1;
-            let raw = unsafe { self.heap_end.offset(-((self.record_count*record_size) as isize)) };
-            let mut record = unsafe { mem::transmute::<_, &mut Record>(raw) };
-            info!("Record: {:p}", record);
-            record.addr = result;
-            record.size = size;
-            record.status = RecordStatus::Referred;
-            info!("Allocate from free, {:?}", record);
-            return Some(result);
+    /// allocate raw memory under GC's control, the address is a multiple of `align`
+    ///
+    /// `align` must be a power of two.
+    pub fn malloc_aligned(&mut self, size: usize, align: usize) -> Option<*const u8> {
+        self.malloc_kind(size, align, RecordKind::Conservative)
+    }
+
+    /// allocate raw memory for data without any pointers, aligned for pointer sized data
+    ///
+    /// The object is kept alive like any other, but its content is never scanned,
+    /// so big buffers don't cost marking time or keep garbage alive by accident.
+    pub fn malloc_atomic(&mut self, size: usize) -> Option<*const u8> {
+        self.malloc_kind(size, mem::align_of::<usize>(), RecordKind::Atomic)
+    }
+
+    /// allocate raw memory whose pointers sit where `descriptor` says, aligned for pointer sized data
+    ///
+    /// Only those words are scanned for pointers, the rest can hold anything
+    /// without keeping garbage alive.
+    pub fn malloc_typed(&mut self, size: usize, descriptor: Descriptor) -> Option<*const u8> {
+        self.malloc_kind(size, mem::align_of::<usize>(), RecordKind::Typed(descriptor))
+    }
+
+    fn malloc_kind(&mut self, size: usize, align: usize, kind: RecordKind) -> Option<*const u8> {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+        info!("Try to allocate memory, size {} align {} kind {:?}", size, align, kind);
+        // zero sized objects still get their own address, so record addresses stay unique
+        let size = cmp::max(size, 1);
+
+        // from deallocated memory first, so the free memory is left for the record table
+        let result = self.malloc_from_deallocated(size, align, kind, false);
+        if let Some(addr) = result {
+            info!("Allocate from deallocated, {:?}", addr);
+            return result;
         }
 
-        // from deallocated memory
-        let result = self.malloc_from_deallocated(size);
+        // from free memory
+        let result = self.malloc_from_free(size, align, kind);
         if result.is_some() {
-            info!("Allocate from deallocated, {:?}", result.unwrap());
             return result;
         }
 
-        // try to cleanup
+        // try to cleanup, trailing deallocated blocks go back to free memory
         self.cleanup();
-        let result = self.malloc_from_deallocated(size);
-        if result.is_some() {
-            info!("Allocate from deallocated after cleanup, {:?}", result.unwrap());
+        let mut result = self.malloc_from_deallocated(size, align, kind, true)
+            .or_else(|| self.malloc_from_free(size, align, kind));
+
+        // grow with a chunk big enough for this request
+        if let (None, Some(policy)) = (result, self.growth) {
+            let needed = size.checked_add(align).and_then(|needed| needed.checked_add(mem::size_of::<Record>()));
+            if needed.is_some_and(|needed| self.grow(cmp::max(policy.chunk_size, needed))) {
+                result = self.malloc_from_free(size, align, kind);
+            }
+        }
+
+        if let Some(addr) = result {
+            info!("Allocate after cleanup, {:?}", addr);
         } else {
             info!("No memory after cleanup :(");
         }
-        return result;
+        result
+    }
+
+    /// bump allocate from the first chunk with enough free memory
+    fn malloc_from_free(&mut self, size: usize, align: usize, kind: RecordKind) -> Option<*const u8> {
+        let blacklist = &self.blacklist;
+        self.chunks[..self.chunk_count].iter_mut()
+            .filter_map(|chunk| chunk.malloc_from_free(size, align, kind, blacklist))
+            .next()
+    }
+
+    /// allocate a `T` under GC's control and move `value` into it
+    ///
+    /// Size and alignment come from `T`, `None` if there is no memory even after cleanup.
+    /// The object lives as long as a cleanup finds a pointer to it, the `GcPtr` alone
+    /// doesn't ensure that: it dangles once the object is collected or the `Gc` dropped.
+    pub fn alloc<T>(&mut self, value: T) -> Option<GcPtr<T>> {
+        let raw = self.malloc_aligned(mem::size_of::<T>(), mem::align_of::<T>())? as *mut T;
+        unsafe { ptr::write(raw, value) };
+        Some(GcPtr { ptr: raw, _marker: PhantomData })
+    }
+
+    /// allocate a `T` under GC's control, its pointers are found through its `Trace` impl
+    ///
+    /// Like `alloc`, but the mark phase calls `T::trace` instead of scanning every word.
+    /// The object lives under the same terms as with `alloc`.
+    pub fn alloc_traced<T: Trace>(&mut self, value: T) -> Option<GcPtr<T>> {
+        let kind = RecordKind::Traced(trace_erased::<T>);
+        let raw = self.malloc_kind(mem::size_of::<T>(), mem::align_of::<T>(), kind)? as *mut T;
+        unsafe { ptr::write(raw, value) };
+        Some(GcPtr { ptr: raw, _marker: PhantomData })
+    }
+
+    /// allocate from the free lists, the unused tail of the block is split into a new record
+    ///
+    /// Every block in the bins from `size` rounded up to a power of two is big enough,
+    /// so it's normally the head of the first non-empty one.
+    /// The bin `size` itself falls in is only walked when those are exhausted.
+    ///
+    /// Splitting takes a spare record slot in the block's chunk, without one
+    /// the whole block is handed out if `whole` is set,
+    /// otherwise it's left for after the next cleanup compacts the record table.
+    fn malloc_from_deallocated(&mut self, size: usize, align: usize, kind: RecordKind, whole: bool) -> Option<*const u8> {
+        let addr = (request_class(size)..BIN_COUNT)
+            .filter_map(|bin| self.take_block(bin, size, align))
+            .next()
+            .or_else(|| self.take_block(size_class(size), size, align))?;
+        let chunk = self.chunk_of(addr).expect("free block outside of the heap");
+        let index = self.chunks[chunk].record_index(addr).expect("free block without a record");
+        let record = unsafe { &mut *self.chunks[chunk].record_ptr(index) };
+
+        // keep the tail pointer aligned, a tail smaller than its own record isn't worth keeping
+        let block_end = addr as usize + record.size;
+        let tail = (addr as usize + size + mem::align_of::<usize>() - 1) & !(mem::align_of::<usize>() - 1);
+        let split = tail + mem::size_of::<Record>() <= block_end;
+        if split && !whole && !self.chunks[chunk].has_record_room() {
+            self.push_block(addr, record.size);
+            return None;
+        }
+
+        record.status = RecordStatus::Referred;
+        record.kind = kind;
+        // don't leave the free list links around for the scanner
+        unsafe { ptr::write_bytes(addr as *mut u8, 0, cmp::min(mem::size_of::<FreeBlock>(), record.size)) };
+
+        if split && self.chunks[chunk].has_record_room() {
+            record.size = size;
+            info!("Split deallocated block at {:#x}, {} bytes left", tail, block_end - tail);
+            self.chunks[chunk].insert_record(index + 1, Record {
+                addr: tail as *const u8,
+                size: block_end - tail,
+                status: RecordStatus::Deallocated,
+                kind: RecordKind::Conservative,
+            });
+            self.push_block(tail as *const u8, block_end - tail);
+        }
+        Some(addr)
+    }
+
+    /// put every deallocated block into the free list of its size class, lowest address first
+    fn rebuild_bins(&mut self) {
+        self.bins = [ptr::null(); BIN_COUNT];
+        for chunk in (0..self.chunk_count).rev() {
+            let chunk = self.chunks[chunk];
+            for index in (1..chunk.record_count+1).rev() {
+                let record = unsafe { &*chunk.record_ptr(index) };
+                if record.status == RecordStatus::Deallocated {
+                    self.push_block(record.addr, record.size);
+                }
+            }
+        }
+    }
+
+    fn push_block(&mut self, addr: *const u8, size: usize) {
+        debug_assert!(size >= mem::size_of::<FreeBlock>());
+        let bin = size_class(size);
+        let block = FreeBlock { next: self.bins[bin], size };
+        unsafe { ptr::write_unaligned(addr as *mut FreeBlock, block) };
+        self.bins[bin] = addr;
+    }
+
+    /// unlink the first block of `bin` which can hold `size` bytes at `align` and isn't blacklisted
+    fn take_block(&mut self, bin: usize, size: usize, align: usize) -> Option<*const u8> {
+        let mut prev: Option<*const u8> = None;
+        let mut current = self.bins[bin];
+        while !current.is_null() {
+            let block = unsafe { ptr::read_unaligned(current as *const FreeBlock) };
+            if block.size >= size && current as usize & (align - 1) == 0 &&
+               self.blacklist.last_in(current, size).is_none() {
+                match prev {
+                    // `next` is the first field of `FreeBlock`
+                    Some(p) => unsafe { ptr::write_unaligned(p as *mut *const u8, block.next) },
+                    None => self.bins[bin] = block.next,
+                }
+                return Some(current);
+            }
+            prev = Some(current);
+            current = block.next;
+        }
+        None
+    }
+
+    /// index of the chunk `addr` points into
+    fn chunk_of(&self, addr: *const u8) -> Option<usize> {
+        self.chunks().iter().position(|chunk| chunk.contains(addr))
+    }
+
+    /// Try to use arbitrary memory address to find corresponding GC Record, with its chunk
+    #[allow(clippy::mut_from_ref)]
+    fn find_record(&self, addr: *const u8) -> Option<(&Chunk, &mut Record)> {
+        let chunk = self.chunks().iter().find(|chunk| chunk.contains(addr))?;
+        chunk.find_record(addr).map(|record| (chunk, record))
+    }
+
+    /// Start of the allocated object `addr` points into
+    ///
+    /// `None` for addresses outside the heap, in gaps between objects
+    /// or in objects which have been deallocated.
+    pub fn object_base(&self, addr: *const u8) -> Option<*const u8> {
+        self.find_record(addr).map(|(_, record)| record.addr)
+    }
+
+    /// whether `addr` points into free memory or a deallocated block of some chunk
+    fn is_free_memory(&self, addr: *const u8) -> bool {
+        self.chunks().iter().any(|chunk| {
+            if chunk.contains(addr) {
+                chunk.record_at(addr).is_some_and(|record| record.status == RecordStatus::Deallocated)
+            } else {
+                chunk.free as usize <= addr as usize &&
+                    (addr as usize) < chunk.record_ptr(chunk.record_count) as usize
+            }
+        })
+    }
+
+    /// mark an allocated record, false if it's already marked in this cycle
+    fn try_mark(&self, chunk: &Chunk, record: &mut Record) -> bool {
+        if self.side_marks {
+            if chunk.is_marked(record) {
+                return false;
+            }
+            chunk.set_marked(record);
+        } else {
+            if record.status != RecordStatus::Unknown {
+                return false;
+            }
+            record.status = RecordStatus::Touched;
+        }
+        true
+    }
+
+    /// whether an allocated record survives the sweep
+    fn is_live(&self, chunk: &Chunk, record: &Record) -> bool {
+        if self.side_marks {
+            chunk.is_marked(record)
+        } else {
+            record.status != RecordStatus::Unknown
+        }
+    }
+
+    fn scan_touch(&mut self, begin: *const u8, end: *const u8) {
+        info!("Marking from {:p} to {:p}", begin, end);
+        let word = mem::size_of::<usize>();
+        let (first, step) = if self.scan_unaligned {
+            (begin as usize, 1)
+        } else {
+            let align = mem::align_of::<usize>();
+            ((begin as usize + align - 1) & !(align - 1), word)
+        };
+        // only words lying completely inside the range
+        let last = (end as usize).saturating_sub(word - 1);
+        // the Gc itself may live in a scanned range, its chunk bounds, free list heads and
+        // blacklist entries point into free memory and would keep themselves blacklisted
+        let own = self as *const Gc as usize;
+        let own_end = own + mem::size_of::<Gc>();
+        for ptr in (first..last).step_by(step) {
+            if ptr + word > own && ptr < own_end {
+                continue;
+            }
+            let value = if self.scan_unaligned {
+                unsafe { ptr::read_unaligned(ptr as *const *const u8) }
+            } else {
+                unsafe { ptr::read(ptr as *const *const u8) }
+            };
+            self.touch(value);
+        }
+    }
+
+    /// mark the object a scanned word points to, or blacklist it if it points into free memory
+    fn touch(&mut self, value: *const u8) {
+        let (chunk, record) = match self.find_record(value) {
+            Some(found) if self.pointer_policy.accepts(value as usize - found.1.addr as usize) => found,
+            Some(_) => return,
+            None => {
+                if self.blacklist.cycles > 0 && self.is_free_memory(value) {
+                    self.blacklist.add(value);
+                }
+                return;
+            }
+        };
+        if self.try_mark(chunk, record) {
+            let record = record as *mut Record;
+            self.mark_push(record);
+        }
+    }
+
+    /// mark the object `addr` points to, precise pointers don't go through the pointer policy
+    fn mark_object(&mut self, addr: *const u8) {
+        if let Some((chunk, record)) = self.find_record(addr) {
+            if self.try_mark(chunk, record) {
+                let record = record as *mut Record;
+                self.mark_push(record);
+            }
+        }
+    }
+
+    /// remember a touched record to be scanned, flag the overflow if there is no room left
+    fn mark_push(&mut self, record: *mut Record) {
+        if self.mark_top == MARK_STACK_SIZE {
+            self.mark_overflow = true;
+            return;
+        }
+        self.mark_stack[self.mark_top] = record;
+        self.mark_top += 1;
+    }
+
+    /// scan touched records until the mark stack is empty
+    fn drain_mark_stack(&mut self) {
+        while self.mark_top > 0 {
+            self.mark_top -= 1;
+            let record = self.mark_stack[self.mark_top];
+            if !self.side_marks {
+                unsafe { (*record).status = RecordStatus::Referred };
+            }
+            self.scan_record(record);
+        }
+    }
+
+    /// look for pointers inside a marked object, the way its kind allows
+    fn scan_record(&mut self, record: *const Record) {
+        let record = unsafe { &*record };
+        match record.kind {
+            RecordKind::Conservative => self.scan_touch(record.addr, unsafe { record.addr.add(record.size) }),
+            RecordKind::Atomic => {}
+            RecordKind::Typed(descriptor) => {
+                let words = record.addr as *const *const u8;
+                for index in 0..record.size / mem::size_of::<usize>() {
+                    if descriptor.is_pointer(index) {
+                        self.touch(unsafe { ptr::read(words.add(index)) });
+                    }
+                }
+            }
+            RecordKind::Traced(trace) => unsafe { trace(record.addr, &mut Tracer { gc: self }) },
+        }
+    }
+
+    /// deallocate GC's record
+    fn free_record(&self, record: &mut Record) {
+        record.status = RecordStatus::Deallocated;
+        info!("Deallocated: {:?}", record);
+        // TODO: clean to zero
+    }
+}
+
+impl Blacklist {
+    /// remember `addr` for the next `cycles` cleanups, or refresh it
+    fn add(&mut self, addr: *const u8) {
+        if let Some(entry) = self.entries[..self.len].iter_mut().find(|entry| entry.0 == addr) {
+            entry.1 = self.cycles;
+            return;
+        }
+        if self.len == BLACKLIST_SIZE {
+            info!("Blacklist is full, ignore {:p}", addr);
+            return;
+        }
+        info!("Blacklist {:p}", addr);
+        self.entries[self.len] = (addr, self.cycles);
+        self.len += 1;
+    }
+
+    /// count down one cleanup, forget the addresses which have run out
+    fn age(&mut self) {
+        let mut kept = 0;
+        for index in 0..self.len {
+            let (addr, cycles) = self.entries[index];
+            if cycles > 1 {
+                self.entries[kept] = (addr, cycles - 1);
+                kept += 1;
+            }
+        }
+        self.len = kept;
+    }
+
+    /// highest blacklisted address in the `size` bytes from `addr`
+    fn last_in(&self, addr: *const u8, size: usize) -> Option<*const u8> {
+        self.entries[..self.len].iter()
+            .map(|entry| entry.0)
+            .filter(|&entry| addr as usize <= entry as usize && (entry as usize) < addr as usize + size)
+            .max()
+    }
+}
+
+impl PointerPolicy {
+    /// whether a pointer `offset` bytes into an object refers to it
+    fn accepts(&self, offset: usize) -> bool {
+        match *self {
+            PointerPolicy::BaseOnly => offset == 0,
+            PointerPolicy::Window(size) => offset < size,
+            PointerPolicy::Interior => true,
+        }
+    }
+}
+
+impl Chunk {
+    /// get a `size` bytes backing region from the global allocator
+    fn allocate(size: usize) -> Result<Chunk, GcError> {
+        if size < mem::size_of::<Record>() {
+            return Err(GcError::TooSmall);
+        }
+        if !size.is_multiple_of(HEAP_ALIGN) {
+            return Err(GcError::Misaligned);
+        }
+        let layout = Layout::from_size_align(size, HEAP_ALIGN).map_err(|_| GcError::TooBig)?;
+        let raw = unsafe { alloc(layout) } as *const u8;
+        if raw.is_null() {
+            return Err(GcError::AllocFailed);
+        }
+        info!("Available memory address {:p} ~ {:p}", raw, unsafe { raw.add(size) });
+        Ok(Chunk {
+            begin: raw,
+            free: raw,
+            end: unsafe { raw.add(size) },
+            size,
+            record_count: 0,
+            marks: ptr::null_mut(),
+        })
+    }
+
+    fn marks_layout(&self) -> Layout {
+        let slots = self.size / mem::size_of::<Record>();
+        unsafe { Layout::from_size_align_unchecked(slots.div_ceil(8), 1) }
     }
 
-    fn malloc_from_deallocated(&self, size: usize) -> Option<*const u8> {
+    /// get the side mark bitmap from the global allocator, one bit per record slot
+    fn allocate_marks(&mut self) -> Result<(), GcError> {
+        if !self.marks.is_null() {
+            return Ok(());
+        }
+        let raw = unsafe { alloc(self.marks_layout()) };
+        if raw.is_null() {
+            return Err(GcError::AllocFailed);
+        }
+        self.marks = raw;
+        Ok(())
+    }
+
+    fn release_marks(&mut self) {
+        if !self.marks.is_null() {
+            unsafe { dealloc(self.marks, self.marks_layout()) };
+            self.marks = ptr::null_mut();
+        }
+    }
+
+    /// unmark every record, only the bytes covering the current records are touched
+    fn clear_marks(&self) {
+        unsafe { ptr::write_bytes(self.marks, 0, self.record_count.div_ceil(8)) };
+    }
+
+    /// byte and mask of the mark bit for `record`, bit `i - 1` belongs to the `i`-th record
+    ///
+    /// Indices only shift while inserting and compacting, never during a collection,
+    /// so the bits stay valid from the clear to the sweep.
+    fn mark_bit(&self, record: *const Record) -> (*mut u8, u8) {
+        let index = (self.end as usize - record as usize) / mem::size_of::<Record>() - 1;
+        (unsafe { self.marks.add(index / 8) }, 1 << (index % 8))
+    }
+
+    fn is_marked(&self, record: *const Record) -> bool {
+        let (byte, mask) = self.mark_bit(record);
+        unsafe { *byte & mask != 0 }
+    }
+
+    fn set_marked(&self, record: *const Record) {
+        let (byte, mask) = self.mark_bit(record);
+        unsafe { *byte |= mask };
+    }
+
+    fn layout(&self) -> Layout {
+        unsafe { Layout::from_size_align_unchecked(self.size, HEAP_ALIGN) }
+    }
+
+    /// whether `addr` points into the allocated memory, free memory and the record table don't count
+    fn contains(&self, addr: *const u8) -> bool {
+        self.begin as usize <= addr as usize && (addr as usize) < self.free as usize
+    }
+
+    /// bytes in free memory plus deallocated blocks
+    fn free_bytes(&self) -> usize {
+        let free = self.record_ptr(self.record_count) as usize - self.free as usize;
+        (1..self.record_count+1)
+            .map(|i| unsafe { &*self.record_ptr(i) })
+            .filter(|r| r.status == RecordStatus::Deallocated)
+            .fold(free, |sum, r| sum + r.size)
+    }
+
+    /// bump allocate from free memory, skip the padding needed to reach the alignment
+    ///
+    /// Blacklisted addresses are skipped too, the bytes in front of the object
+    /// are left as a gap until the record before it is deallocated,
+    /// or the object itself when it's the first one in the chunk.
+    fn malloc_from_free(&mut self, size: usize, align: usize, kind: RecordKind, blacklist: &Blacklist) -> Option<*const u8> {
         let record_size = mem::size_of::<Record>();
-        let record = (1..self.record_count+1)
-            .map(|i| unsafe { self.heap_end.offset(-((i*record_size) as isize)) })
-            .map(|raw| unsafe { mem::transmute::<_, &mut Record>(raw) })
-            .filter(|r| r.status == RecordStatus::Deallocated && r.size >= size)
-            .take(1)
-            .next();
-        if let Some(r) = record {
-            r.status = RecordStatus::Referred;
-            return Some(r.addr);
+        let mut start = self.free as usize;
+        let result = loop {
+            // requests near the top of the address space can't fit anywhere
+            let result = start.checked_add(align - 1)? & !(align - 1);
+            if result.checked_add(size)?.checked_add(record_size)? > self.record_ptr(self.record_count) as usize {
+                return None;
+            }
+            match blacklist.last_in(result as *const u8, size) {
+                Some(addr) => start = addr as usize + 1,
+                None => break result as *const u8,
+            }
+        };
+        if result != self.free {
+            info!("Skip {} bytes of free memory", result as usize - self.free as usize);
+        }
+
+        self.free = unsafe { result.add(size) };
+        self.record_count += 1;
+        let record = unsafe { &mut *self.record_ptr(self.record_count) };
+        info!("Record: {:p}", record);
+        record.addr = result;
+        record.size = size;
+        record.status = RecordStatus::Referred;
+        record.kind = kind;
+        info!("Allocate from free, {:?}", record);
+        Some(result)
+    }
+
+    /// reclaim record slots after sweep
+    ///
+    /// A deallocated record takes over the gap up to the next record,
+    /// the first one also the gap from the start of the chunk,
+    /// neighbouring deallocated records are merged,
+    /// and slivers too small to be worth a slot are dropped,
+    /// their bytes come back once the record in front of them is deallocated.
+    /// A trailing deallocated block goes back to free memory.
+    fn compact_records(&mut self) {
+        let count = self.record_count;
+
+        // merge
+        let mut kept = 0;
+        for index in 1..count+1 {
+            let mut record = unsafe { ptr::read(self.record_ptr(index)) };
+            if record.status == RecordStatus::Deallocated && index == 1 {
+                record.size += record.addr as usize - self.begin as usize;
+                record.addr = self.begin;
+            }
+            if record.status == RecordStatus::Deallocated && index < count {
+                let next = unsafe { &*self.record_ptr(index + 1) };
+                record.size = next.addr as usize - record.addr as usize;
+            }
+            if kept > 0 {
+                let last = unsafe { &mut *self.record_ptr(kept) };
+                if last.status == RecordStatus::Deallocated &&
+                   record.status == RecordStatus::Deallocated {
+                    last.size += record.size;
+                    continue;
+                }
+            }
+            kept += 1;
+            unsafe { ptr::write(self.record_ptr(kept), record) };
+        }
+        info!("Merged {} deallocated records", count - kept);
+
+        // drop slivers, the last one is left for the trailing block check
+        let merged = kept;
+        kept = 0;
+        for index in 1..merged+1 {
+            let record = unsafe { ptr::read(self.record_ptr(index)) };
+            if record.status == RecordStatus::Deallocated &&
+               record.size < mem::size_of::<Record>() &&
+               index < merged {
+                continue;
+            }
+            kept += 1;
+            unsafe { ptr::write(self.record_ptr(kept), record) };
+        }
+        info!("Reclaimed {} record slots", count - kept);
+        self.record_count = kept;
+
+        if kept > 0 && unsafe { &*self.record_ptr(kept) }.status == RecordStatus::Deallocated {
+            self.record_count -= 1;
+            self.free = if self.record_count > 0 {
+                let last = unsafe { &*self.record_ptr(self.record_count) };
+                unsafe { last.addr.add(last.size) }
+            } else {
+                self.begin
+            };
+            info!("Give trailing block back, free memory starts from {:p}", self.free);
+        }
+    }
+
+    /// index of the record starting exactly at `addr`
+    fn record_index(&self, addr: *const u8) -> Option<usize> {
+        let mut start = 1;
+        let mut end = self.record_count + 1;
+        while start < end {
+            let mid = (start + end) / 2;
+            let record_addr = unsafe { &*self.record_ptr(mid) }.addr;
+            match (record_addr as usize).cmp(&(addr as usize)) {
+                cmp::Ordering::Equal => return Some(mid),
+                cmp::Ordering::Less => start = mid + 1,
+                cmp::Ordering::Greater => end = mid,
+            }
         }
         None
     }
 
+    /// pointer to the `index`-th record, counting from 1 downward from `end`
+    ///
+    /// Records are sorted by address, the lowest address comes first.
+    fn record_ptr(&self, index: usize) -> *mut Record {
+        unsafe { (self.end as *mut Record).sub(index) }
+    }
+
+    /// whether the record table can grow by one without hitting the allocated memory
+    fn has_record_room(&self) -> bool {
+        self.free as usize + mem::size_of::<Record>() <= self.record_ptr(self.record_count) as usize
+    }
+
+    /// put `record` at `index`, shifting the records from `index` one slot down
+    fn insert_record(&mut self, index: usize, record: Record) {
+        debug_assert!(self.has_record_room());
+        debug_assert!(1 <= index && index <= self.record_count + 1);
+        unsafe {
+            ptr::copy(self.record_ptr(self.record_count),
+                      self.record_ptr(self.record_count + 1),
+                      self.record_count + 1 - index);
+            ptr::write(self.record_ptr(index), record);
+        }
+        self.record_count += 1;
+    }
+
     /// Try to use arbitrary memory address to find corresponding GC Record
+    ///
+    /// Only an allocated record with `addr` inside it counts,
+    /// addresses in gaps between records or in deallocated records give `None`.
+    ///
+    /// Records live inside the GC owned heap, not inside `Gc` itself,
+    /// so handing out `&mut` from `&self` doesn't alias any of our fields.
+    #[allow(clippy::mut_from_ref)]
     fn find_record(&self, addr: *const u8) -> Option<&mut Record> {
+        self.record_at(addr).filter(|record| record.status != RecordStatus::Deallocated)
+    }
+
+    /// record with `addr` inside it, deallocated or not
+    #[allow(clippy::mut_from_ref)]
+    fn record_at(&self, addr: *const u8) -> Option<&mut Record> {
         // check memory address is in GC's controlled range
-        if !((self.heap_begin as usize <= addr as usize) &&
-             (addr as usize <= self.heap_end as usize)) {
+        if !self.contains(addr) {
             return None;
         }
 
         info!("Finding Record of address {:?}", addr);
-        let record_size = mem::size_of::<Record>();
 
-        let mut start = 0;
-        let mut end = self.record_count;
-        while end - start > 1 {
+        // the last record starting at or below `addr`, record indices start from 1
+        let mut start = 1;
+        let mut end = self.record_count + 1;
+        while start < end {
             let mid = (start + end) / 2;
-            let raw = unsafe { self.heap_end.offset(-((mid*record_size) as isize)) };
-            let record = unsafe { mem::transmute::<_, &mut Record>(raw) };
-            if addr as usize >= record.addr as usize {
-                if unsafe { record.addr.offset(record.size as isize) } as usize > addr as usize {
-                    return Some(record);
-                } else {
-                    start = mid;
-                }
+            if unsafe { &*self.record_ptr(mid) }.addr as usize <= addr as usize {
+                start = mid + 1;
             } else {
                 end = mid;
             }
         }
+        if start == 1 {
+            return None;
+        }
 
-        let raw = unsafe { self.heap_end.offset(-((start*record_size) as isize)) };
-        let record = unsafe { mem::transmute::<_, &mut Record>(raw) };
-        Some(record)
+        let record = unsafe { &mut *self.record_ptr(start - 1) };
+        if (addr as usize) < record.addr as usize + record.size {
+            Some(record)
+        } else {
+            None
+        }
     }
+}
 
-    fn scan_touch(&self, begin: *const u8, end: *const u8) {
-        info!("Marking from {:p} to {:p}", begin, end);
-        for record in
-            (begin as usize..end as usize)
-                .map(|ptr| unsafe { *(ptr as *const *const u8) })
-                .filter_map(|x| self.find_record(x))
-                .filter(|r| r.status == RecordStatus::Unknown) {
-            record.status = RecordStatus::Touched;
+/// address of a local in a frame of its own, below every frame of the caller
+#[inline(never)]
+fn stack_pointer() -> *const u8 {
+    let marker = 0u8;
+    hint::black_box(&marker) as *const u8
+}
+
+/// `dl_iterate_phdr` callback, scan the writable loaded segments of one object
+#[cfg(all(feature = "linux", target_os = "linux"))]
+unsafe extern "C" fn scan_data_segment(info: *mut libc::dl_phdr_info,
+                                       _size: libc::size_t,
+                                       gc: *mut libc::c_void) -> libc::c_int {
+    let gc = &mut *(gc as *mut Gc);
+    let info = &*info;
+    for index in 0..info.dlpi_phnum as usize {
+        let header = &*info.dlpi_phdr.add(index);
+        if header.p_type == libc::PT_LOAD && header.p_flags & libc::PF_W != 0 {
+            let begin = (info.dlpi_addr as usize + header.p_vaddr as usize) as *const u8;
+            gc.scan_touch(begin, begin.add(header.p_memsz as usize));
         }
     }
+    0
+}
 
-    /// deallocate GC's record
-    fn free_record(&self, record: &mut Record) {
-        record.status = RecordStatus::Deallocated;
-        info!("Deallocated: {:?}", record);
-        // TODO: clean to zero
+/// copy the callee-saved registers into an array on the stack
+///
+/// Caller-saved ones are already on the stack if they are live across `cleanup`,
+/// the callee-saved ones are either still in the registers or saved by a frame we scan.
+/// Other architectures have nothing spilled, only the stack is scanned.
+#[inline(always)]
+fn spill_registers() -> [usize; SPILLED_REGISTERS] {
+    let mut registers = [0; SPILLED_REGISTERS];
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        asm!(
+            "mov [{0}], rbx",
+            "mov [{0} + 8], rbp",
+            "mov [{0} + 16], r12",
+            "mov [{0} + 24], r13",
+            "mov [{0} + 32], r14",
+            "mov [{0} + 40], r15",
+            in(reg) registers.as_mut_ptr(),
+            options(nostack, preserves_flags),
+        );
+    }
+    #[cfg(all(target_arch = "x86_64", target_os = "windows"))]
+    unsafe {
+        asm!(
+            "mov [{0} + 48], rsi",
+            "mov [{0} + 56], rdi",
+            in(reg) registers.as_mut_ptr(),
+            options(nostack, preserves_flags),
+        );
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        asm!(
+            "stp x19, x20, [{0}]",
+            "stp x21, x22, [{0}, #16]",
+            "stp x23, x24, [{0}, #32]",
+            "stp x25, x26, [{0}, #48]",
+            "stp x27, x28, [{0}, #64]",
+            "str x29, [{0}, #80]",
+            in(reg) registers.as_mut_ptr(),
+            options(nostack, preserves_flags),
+        );
+    }
+    registers
+}
+
+/// size class of a block, `floor(log2(size))`
+fn size_class(size: usize) -> usize {
+    (mem::size_of::<usize>() * 8 - 1) - size.leading_zeros() as usize
+}
+
+/// lowest size class whose blocks all hold `size` bytes, `ceil(log2(size))`
+fn request_class(size: usize) -> usize {
+    if size <= 1 { 0 } else { size_class(size - 1) + 1 }
+}
+
+impl Drop for Gc {
+    fn drop(&mut self) {
+        for chunk in &mut self.chunks[..self.chunk_count] {
+            info!("Releasing GC memory {:p} ~ {:p}", chunk.begin, chunk.end);
+            unsafe { dealloc(chunk.begin as *mut u8, chunk.layout()) };
+            chunk.release_marks();
+        }
+
+        // remaining handles unlink from themselves only
+        let mut node = unsafe { (*self.root_handles).next };
+        while node != self.root_handles {
+            let next = unsafe { (*node).next };
+            unsafe {
+                (*node).prev = node;
+                (*node).next = node;
+            }
+            node = next;
+        }
+        drop(unsafe { Box::from_raw(self.root_handles) });
     }
 }
 
+/// # Safety
+///
+/// All zero bytes must be a valid `T`, and the reference must not be used
+/// once the object is unreachable or `gc` is dropped.
+#[doc(hidden)]
+pub unsafe fn __malloc_zeroed<'a, T>(gc: &mut Gc) -> Option<&'a mut T> {
+    let raw = gc.malloc_aligned(mem::size_of::<T>(), mem::align_of::<T>())? as *mut T;
+    ptr::write_bytes(raw, 0, 1);
+    Some(&mut *raw)
+}
+
+/// Allocate memory under GC's control
+///
+/// * `malloc!(gc, size)` allocates `size` bytes and transmutes the address into the annotated type
+/// * `malloc!(gc)` allocates a zeroed `T` for the annotated `&mut T`, the memory must be valid as all zero
+///
+/// Panic if the heap is exhausted even after cleanup, see `try_malloc!` for the fallible version.
+///
+/// # Safety
+///
+/// The expansion is unsafe and has to be wrapped in an `unsafe` block.
+/// The annotated type must be valid for the memory handed out, all zero or `size` uninitialized bytes,
+/// and the reference must not be used once the object is unreachable from the scanned roots
+/// or `gc` is dropped, its lifetime isn't tied to either.
 #[macro_export]
 macro_rules! malloc {
+    ($gc:ident) => ({
+        $crate::__malloc_zeroed(&mut $gc).expect("GC heap exhausted")
+    });
     ($gc:ident, $size:expr) => ({
         use std::mem;
-        let foo = false;
-        $gc.stack_end(&foo);
-        unsafe { mem::transmute($gc.malloc(4096).unwrap()) }
-    })
+        let size = $size;
+        let raw = $gc.malloc(size)
+            .unwrap_or_else(|| panic!("GC heap exhausted while allocating {} bytes", size));
+        #[allow(clippy::transmute_ptr_to_ref)]
+        let ptr = mem::transmute(raw);
+        ptr
+    });
 }
 
+/// `malloc!` for `no_std` users
 #[macro_export]
 macro_rules! malloc_core {
+    ($gc:ident) => ({
+        $crate::__malloc_zeroed(&mut $gc).expect("GC heap exhausted")
+    });
+    ($gc:ident, $size:expr) => ({
+        use core::mem;
+        let size = $size;
+        let raw = $gc.malloc(size)
+            .unwrap_or_else(|| panic!("GC heap exhausted while allocating {} bytes", size));
+        #[allow(clippy::transmute_ptr_to_ref)]
+        let ptr = mem::transmute(raw);
+        ptr
+    });
+}
+
+/// Same as `malloc!` but yield an `Option` instead of panicking when the heap is exhausted
+///
+/// # Safety
+///
+/// Same as `malloc!`.
+#[macro_export]
+macro_rules! try_malloc {
+    ($gc:ident) => ({
+        $crate::__malloc_zeroed(&mut $gc)
+    });
+    ($gc:ident, $size:expr) => ({
+        use std::mem;
+        #[allow(clippy::transmute_ptr_to_ref)]
+        let ptr = $gc.malloc($size).map(|raw| mem::transmute(raw));
+        ptr
+    });
+}
+
+/// `try_malloc!` for `no_std` users
+#[macro_export]
+macro_rules! try_malloc_core {
+    ($gc:ident) => ({
+        $crate::__malloc_zeroed(&mut $gc)
+    });
     ($gc:ident, $size:expr) => ({
         use core::mem;
-        let foo = false;
-        $gc.stack_end(&foo);
-        unsafe { mem::transmute($gc.malloc(4096).unwrap()) }
-    })
+        #[allow(clippy::transmute_ptr_to_ref)]
+        let ptr = $gc.malloc($size).map(|raw| mem::transmute(raw));
+        ptr
+    });
 }
@@ -0,0 +1,46 @@
+extern crate scgc;
+
+mod common;
+
+use common::{gc_without_stack, root_slots};
+use scgc::Gc;
+
+use std::mem;
+
+
+const SIZES: [usize; 5] = [1, 7, 13, 100, 333];
+const ALIGNS: [usize; 3] = [8, 64, 256];
+
+/// allocate every size at every alignment, keeping every other object alive through `roots`
+fn allocate_round(gc: &mut Gc, roots: &mut [*const u8]) -> Vec<*const u8> {
+    let mut addrs = Vec::new();
+    for &align in &ALIGNS {
+        for &size in &SIZES {
+            let addr = gc.malloc_aligned(size, align).expect("GC heap exhausted");
+            assert_eq!(addr as usize % align, 0, "{} bytes at alignment {}", size, align);
+            if addrs.len() % 2 == 0 {
+                roots[addrs.len() / 2] = addr;
+            }
+            addrs.push(addr);
+        }
+        let addr = gc.malloc(13).expect("GC heap exhausted");
+        assert_eq!(addr as usize % mem::align_of::<usize>(), 0);
+        addrs.push(addr);
+    }
+    addrs
+}
+
+#[test]
+fn allocations_are_aligned() {
+    let mut gc = gc_without_stack(1 << 16);
+    let mut roots = root_slots(&mut gc, 16);
+
+    // from bump memory
+    let first = allocate_round(&mut gc, &mut roots);
+    let top = first.iter().map(|&addr| addr as usize).max().unwrap();
+    gc.cleanup();
+
+    // the dead objects left holes, some of this round reuses them
+    let second = allocate_round(&mut gc, &mut roots);
+    assert!(second.iter().any(|&addr| (addr as usize) < top));
+}
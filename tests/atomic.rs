@@ -0,0 +1,35 @@
+extern crate scgc;
+
+mod common;
+
+use common::{alive, gc_without_stack, root_slots, Hidden};
+use scgc::Gc;
+
+use std::ptr;
+
+
+/// Put the only pointer to a child into a parent allocated by `parent`,
+/// root the parent outside the stack and tell whether the child survives a cleanup
+fn child_survives(parent: fn(&mut Gc, usize) -> Option<*const u8>) -> bool {
+    let mut gc = gc_without_stack(1 << 16);
+    let mut roots = root_slots(&mut gc, 1);
+
+    roots[0] = parent(&mut gc, 64).expect("GC heap exhausted");
+    let child = gc.malloc(64).expect("GC heap exhausted");
+    unsafe { ptr::write_bytes(child as *mut u8, 0, 64) };
+    unsafe { ptr::write(roots[0] as *mut *const u8, child) };
+    let child = Hidden::new(child);
+    gc.cleanup();
+
+    alive(&gc, roots[0]) && alive(&gc, child.get())
+}
+
+#[test]
+fn atomic_objects_are_not_scanned() {
+    assert!(!child_survives(Gc::malloc_atomic));
+}
+
+#[test]
+fn plain_objects_are_scanned() {
+    assert!(child_survives(Gc::malloc));
+}
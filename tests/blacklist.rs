@@ -0,0 +1,137 @@
+extern crate scgc;
+
+mod common;
+
+use common::{gc_without_stack, root_slots, Hidden};
+use scgc::Gc;
+
+use std::mem;
+use std::ptr;
+
+
+/// Run `cleanups` cleanups with a stray pointer 8 bytes into the free memory
+/// behind a dead 64 bytes object, dropping the stray after the first one,
+/// then allocate two objects of that size in the same place again.
+///
+/// Yields the stray address and where the second object ended up.
+fn bump_after_stray(cycles: usize, cleanups: usize) -> (*const u8, *const u8) {
+    let mut gc = Gc::new(1 << 16);
+    gc.set_blacklist_cycles(cycles);
+    let mut roots = [ptr::null::<u8>(); 2];
+    gc.stack_begin(&roots[0]);
+    gc.stack_end(&roots[1]);
+
+    let first = gc.malloc(64).expect("GC heap exhausted");
+    roots[0] = unsafe { first.add(64 + 8) };
+    let hidden = Hidden::new(first);
+    for _ in 0..cleanups {
+        gc.cleanup();
+        roots[0] = ptr::null();
+    }
+
+    // the dead object went back to free memory, so both come from bumping again
+    let first = hidden.get();
+    assert_eq!(gc.malloc(64), Some(first));
+    (unsafe { first.add(64 + 8) }, gc.malloc(64).expect("GC heap exhausted"))
+}
+
+fn covers(addr: *const u8, size: usize, stray: *const u8) -> bool {
+    addr as usize <= stray as usize && (stray as usize) < addr as usize + size
+}
+
+#[test]
+fn bump_allocation_skips_stray_pointers() {
+    let (stray, second) = bump_after_stray(1, 1);
+    assert!(!covers(second, 64, stray));
+}
+
+#[test]
+fn blacklisting_is_off_by_default() {
+    let (stray, second) = bump_after_stray(0, 1);
+    assert!(covers(second, 64, stray));
+}
+
+#[test]
+fn blacklisted_addresses_expire() {
+    let (stray, second) = bump_after_stray(2, 2);
+    assert!(!covers(second, 64, stray));
+    let (stray, second) = bump_after_stray(2, 3);
+    assert!(covers(second, 64, stray));
+}
+
+#[test]
+fn free_lists_skip_stray_pointers() {
+    let mut gc = Gc::new(1 << 16);
+    gc.set_blacklist_cycles(1);
+    let mut roots = [ptr::null::<u8>(); 3];
+    gc.stack_begin(&roots[0]);
+    gc.stack_end(&roots[2]);
+
+    // the dead object stays a deallocated block, the live one after it keeps it off free memory
+    let dead = gc.malloc(64).expect("GC heap exhausted");
+    roots[1] = gc.malloc(64).expect("GC heap exhausted");
+    roots[0] = unsafe { dead.add(8) };
+    gc.cleanup();
+
+    let addr = gc.malloc(64).expect("GC heap exhausted");
+    assert!(!covers(addr, 64, roots[0]));
+}
+
+#[test]
+fn scanning_the_gc_itself_does_not_keep_entries() {
+    let mut gc = Box::new(gc_without_stack(1 << 16));
+    gc.set_blacklist_cycles(1);
+    let mut roots = root_slots(&mut gc, 2);
+    // the Gc's own fields point into free memory, they must not refresh the blacklist
+    let own = &*gc as *const Gc as *const u8;
+    gc.add_roots(own, unsafe { own.add(mem::size_of::<Gc>()) }).expect("no room for roots");
+
+    let dead = gc.malloc(64).expect("GC heap exhausted");
+    roots[1] = gc.malloc(64).expect("GC heap exhausted");
+    roots[0] = unsafe { dead.add(8) };
+    let dead = Hidden::new(dead);
+    gc.cleanup();
+    roots[0] = ptr::null();
+    for _ in 0..3 {
+        gc.cleanup();
+    }
+
+    assert_eq!(gc.malloc(64), Some(dead.get()));
+}
+
+/// Bump an object past a blacklisted stray 8 bytes into the empty heap,
+/// yield where the heap starts and where the object went.
+///
+/// Kept out of line, so no copy of those addresses stays behind in the caller's registers.
+#[inline(never)]
+fn bump_past_stray(gc: &mut Gc, stray: &mut *const u8) -> (Hidden, Hidden) {
+    // the heap is empty again after this, bumping starts from the same place
+    let begin = Hidden::new(gc.malloc(64).expect("GC heap exhausted"));
+    gc.cleanup();
+    *stray = unsafe { begin.get().add(8) };
+    gc.cleanup();
+    *stray = ptr::null();
+
+    let first = gc.malloc(64).expect("GC heap exhausted");
+    assert!(first as usize > begin.get() as usize + 8);
+    (begin, Hidden::new(first))
+}
+
+#[test]
+fn gap_skipped_ahead_of_the_first_record_is_reclaimed() {
+    let mut gc = gc_without_stack(1 << 16);
+    gc.set_blacklist_cycles(1);
+    let mut roots = root_slots(&mut gc, 2);
+
+    // the live object after the first one keeps it off free memory
+    let (begin, first) = bump_past_stray(&mut gc, &mut roots[0]);
+    roots[1] = gc.malloc(64).expect("GC heap exhausted");
+    for _ in 0..3 {
+        gc.cleanup();
+    }
+
+    assert_eq!(gc.object_base(first.get()), None);
+    // the skipped bytes come back along with the dead first object
+    let gap = first.get() as usize - begin.get() as usize;
+    assert_eq!(gc.malloc(64 + gap), Some(begin.get()));
+}
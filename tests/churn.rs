@@ -0,0 +1,56 @@
+extern crate scgc;
+
+mod common;
+
+use common::{alive, gc_without_stack, root_slots};
+use scgc::Gc;
+
+use std::ptr;
+
+
+/// Many more objects than a small heap has record slots for, only a few of them live at a time.
+/// Without reclaiming the slots of dead records the record table would eat up the heap.
+///
+/// Kept out of line, so no copy of an address stays behind in the caller's registers.
+#[inline(never)]
+fn churn(gc: &mut Gc, roots: &mut [*const u8]) {
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    for round in 0..100_000 {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let size = 8 + (seed >> 33) as usize % 120;
+        let addr = gc.malloc(size).unwrap_or_else(|| panic!("GC heap exhausted in round {}", round));
+        unsafe { ptr::write_bytes(addr as *mut u8, 0, size) };
+        if round % 16 == 0 {
+            roots[(seed >> 13) as usize % roots.len()] = addr;
+        }
+    }
+
+    gc.cleanup();
+    assert!(roots.iter().all(|&root| root.is_null() || alive(gc, root)));
+}
+
+#[test]
+fn long_churn_fits_a_small_heap() {
+    let mut gc = gc_without_stack(1 << 14);
+    let mut roots = root_slots(&mut gc, 8);
+    churn(&mut gc, &mut roots);
+
+    // with nothing live the record table shrinks back and most of the heap is one block again
+    roots.iter_mut().for_each(|root| *root = ptr::null());
+    gc.cleanup();
+    assert!(gc.malloc(12 << 10).is_some());
+}
+
+/// One small object replaced after every cleanup, each cleanup leaves the dead one
+/// in front of the live one, at the very start of the heap.
+#[test]
+fn leading_dead_objects_are_reclaimed() {
+    let mut gc = gc_without_stack(1 << 12);
+    let mut roots = root_slots(&mut gc, 1);
+
+    for round in 0..10_000 {
+        roots[0] = gc.malloc(8).unwrap_or_else(|| panic!("GC heap exhausted in round {}", round));
+        gc.cleanup();
+    }
+    assert!(gc.malloc(2048).is_some());
+}
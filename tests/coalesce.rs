@@ -0,0 +1,36 @@
+extern crate scgc;
+
+mod common;
+
+use common::{alive, gc_without_stack, root_slots, Hidden};
+
+
+#[test]
+fn neighbouring_dead_blocks_are_merged() {
+    let mut gc = gc_without_stack(1 << 16);
+    let mut roots = root_slots(&mut gc, 1);
+
+    // neither block alone holds 128 bytes, the live object after them keeps them off free memory
+    let first = Hidden::new(gc.malloc(64).expect("GC heap exhausted"));
+    gc.malloc(64).expect("GC heap exhausted");
+    roots[0] = gc.malloc(64).expect("GC heap exhausted");
+    gc.cleanup();
+
+    assert_eq!(gc.malloc(128), Some(first.get()));
+    assert!(alive(&gc, roots[0]));
+}
+
+#[test]
+fn trailing_dead_block_goes_back_to_free_memory() {
+    let mut gc = gc_without_stack(1 << 16);
+    let mut roots = root_slots(&mut gc, 1);
+
+    roots[0] = gc.malloc(64).expect("GC heap exhausted");
+    let trailing = Hidden::new(gc.malloc(64).expect("GC heap exhausted"));
+    gc.cleanup();
+
+    assert_eq!(gc.object_base(trailing.get()), None);
+    // too big for the old block, it's bumped from where that block started
+    assert_eq!(gc.malloc(200), Some(trailing.get()));
+    assert!(alive(&gc, roots[0]));
+}
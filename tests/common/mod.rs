@@ -0,0 +1,48 @@
+//! Fixtures shared by the integration tests
+
+#![allow(dead_code)]
+
+use scgc::Gc;
+
+use std::hint::black_box;
+use std::ptr;
+
+
+/// A GC whose stack range is empty, only roots registered on it keep objects alive
+pub fn gc_without_stack(size: usize) -> Gc {
+    let mut gc = Gc::new(size);
+    let stack = 0usize;
+    gc.stack_begin(&stack);
+    gc.stack_end(&stack);
+    gc
+}
+
+/// `count` null root slots outside the stack, registered on `gc`
+pub fn root_slots(gc: &mut Gc, count: usize) -> Box<[*const u8]> {
+    let roots = vec![ptr::null::<u8>(); count].into_boxed_slice();
+    let range = roots.as_ptr_range();
+    gc.add_roots(range.start as *const u8, range.end as *const u8).expect("no room for roots");
+    roots
+}
+
+/// whether `addr` is still the start of an allocated object
+pub fn alive(gc: &Gc, addr: *const u8) -> bool {
+    gc.object_base(addr) == Some(addr)
+}
+
+/// An address kept as its complement
+///
+/// A copy of the plain address in a register or on the stack would keep the object
+/// alive, which a test checking that it gets collected can't have.
+#[derive(Clone, Copy)]
+pub struct Hidden(usize);
+
+impl Hidden {
+    pub fn new(addr: *const u8) -> Hidden {
+        Hidden(black_box(!(addr as usize)))
+    }
+
+    pub fn get(&self) -> *const u8 {
+        !self.0 as *const u8
+    }
+}
@@ -0,0 +1,87 @@
+extern crate quickcheck;
+extern crate scgc;
+
+use quickcheck::quickcheck;
+use scgc::Gc;
+
+use std::ptr;
+
+
+const HEAP: usize = 1 << 16;
+const OBJECTS: usize = 64;
+
+
+/// Object `addr` should resolve to, given the objects which are still alive
+fn expected_base(objects: &[(*const u8, usize)], addr: usize) -> Option<*const u8> {
+    objects.iter()
+        .find(|&&(base, size)| base as usize <= addr && addr < base as usize + size)
+        .map(|&(base, _)| base)
+}
+
+/// Fill a fresh heap with `sizes` objects, keep the ones flagged in `keep`
+/// and probe arbitrary addresses across the whole heap, the record table included,
+/// before and after a cleanup.
+///
+/// The objects don't fill the heap, so they are all bump allocated
+/// and their records are exactly as big as requested.
+fn lookup_matches_model(sizes: Vec<u16>, keep: Vec<bool>, probes: Vec<u32>) -> bool {
+    let mut gc = Gc::new(HEAP);
+    let mut roots = [ptr::null::<u8>(); OBJECTS + 1];
+    gc.stack_begin(&roots[0]);
+    gc.stack_end(&roots[OBJECTS]);
+
+    let mut allocated = Vec::new();
+    for (i, &size) in sizes.iter().take(OBJECTS).enumerate() {
+        let size = (size % 512) as usize;
+        let addr = gc.malloc(size).expect("GC heap exhausted");
+        // zero sized objects take a byte, and no stale pointers for the scanner
+        let size = std::cmp::max(size, 1);
+        unsafe { ptr::write_bytes(addr as *mut u8, 0, size) };
+        allocated.push((addr, size));
+        if keep.get(i).cloned().unwrap_or(false) {
+            roots[i] = addr;
+        }
+    }
+    let heap_begin = match allocated.first() {
+        Some(&(addr, _)) => addr as usize,
+        None => return true,
+    };
+
+    let probe = |gc: &Gc, objects: &[(*const u8, usize)]| {
+        probes.iter()
+            .map(|&p| heap_begin - 64 + p as usize % (HEAP + 128))
+            .chain(objects.iter().map(|&(base, size)| base as usize + size))
+            .all(|addr| gc.object_base(addr as *const u8) == expected_base(objects, addr))
+    };
+
+    if !probe(&gc, &allocated) {
+        return false;
+    }
+
+    gc.cleanup();
+    let live: Vec<_> = allocated.iter().cloned()
+        .filter(|&(base, _)| roots.contains(&base))
+        .collect();
+    probe(&gc, &live)
+}
+
+#[test]
+fn lookup_resolves_only_live_objects() {
+    quickcheck(lookup_matches_model as fn(Vec<u16>, Vec<bool>, Vec<u32>) -> bool);
+}
+
+#[test]
+fn lookup_ignores_free_memory_and_record_table() {
+    let mut gc = Gc::new(HEAP);
+    let roots = [ptr::null::<u8>(); 2];
+    gc.stack_begin(&roots[0]);
+    gc.stack_end(&roots[1]);
+
+    let addr = gc.malloc(16).expect("GC heap exhausted");
+    assert_eq!(gc.object_base(addr), Some(addr));
+    assert_eq!(gc.object_base(unsafe { addr.add(15) }), Some(addr));
+    // the first byte of free memory up to the last byte of the heap, where the record is
+    for offset in 16..HEAP {
+        assert_eq!(gc.object_base(unsafe { addr.add(offset) }), None);
+    }
+}
@@ -0,0 +1,28 @@
+extern crate scgc;
+
+use scgc::{Gc, GcError};
+
+
+#[test]
+fn heap_must_hold_a_record() {
+    assert_eq!(Gc::try_new(0).err(), Some(GcError::TooSmall));
+    assert_eq!(Gc::try_new(8).err(), Some(GcError::TooSmall));
+}
+
+#[test]
+fn heap_size_must_be_aligned() {
+    assert_eq!(Gc::try_new(4097).err(), Some(GcError::Misaligned));
+}
+
+#[test]
+fn huge_heaps_are_reported() {
+    assert_eq!(Gc::try_new(usize::MAX & !7).err(), Some(GcError::TooBig));
+    // a valid layout, but no allocator has that much
+    assert_eq!(Gc::try_new(1 << 62).err(), Some(GcError::AllocFailed));
+}
+
+#[test]
+#[should_panic(expected = "GC heap size is more than")]
+fn new_panics_with_the_error() {
+    Gc::new(usize::MAX & !7);
+}
@@ -0,0 +1,106 @@
+extern crate scgc;
+
+mod common;
+
+use common::{alive, gc_without_stack, root_slots, Hidden};
+use scgc::{Gc, GrowthPolicy};
+
+use std::ptr;
+
+
+/// mirrors the crate's chunk limit, the initial heap being the first one
+const MAX_CHUNKS: usize = 16;
+
+fn growing_gc(size: usize, chunk_size: usize, min_free_percent: usize) -> Gc {
+    let mut gc = gc_without_stack(size);
+    gc.set_growth_policy(Some(GrowthPolicy { chunk_size, min_free_percent }));
+    gc
+}
+
+/// allocate `size` bytes linked in front of `*head`, the first word points to the previous head
+fn push(gc: &mut Gc, head: &mut *const u8, size: usize) -> bool {
+    match gc.malloc(size) {
+        Some(addr) => {
+            unsafe { ptr::write_bytes(addr as *mut u8, 0, size) };
+            unsafe { ptr::write(addr as *mut *const u8, *head) };
+            *head = addr;
+            true
+        }
+        None => false,
+    }
+}
+
+/// addresses of the objects linked from `head`, newest first
+fn walk(mut head: *const u8) -> Vec<*const u8> {
+    let mut objects = Vec::new();
+    while !head.is_null() {
+        objects.push(head);
+        head = unsafe { ptr::read(head as *const *const u8) };
+    }
+    objects
+}
+
+#[test]
+fn heap_without_policy_keeps_its_size() {
+    let mut gc = gc_without_stack(1 << 12);
+    let mut roots = root_slots(&mut gc, 1);
+    let count = (0..).take_while(|_| push(&mut gc, &mut roots[0], 64)).count();
+    assert!(count > 0 && count * 64 < 1 << 12);
+}
+
+#[test]
+fn heap_grows_for_live_objects() {
+    let mut gc = growing_gc(1 << 12, 1 << 14, 25);
+    let mut roots = root_slots(&mut gc, 1);
+    for _ in 0..1000 {
+        assert!(push(&mut gc, &mut roots[0], 64), "GC heap exhausted");
+    }
+    gc.cleanup();
+
+    // lookups find objects in every chunk
+    let objects = walk(roots[0]);
+    assert_eq!(objects.len(), 1000);
+    for &object in &objects {
+        assert_eq!(gc.object_base(unsafe { object.add(40) }), Some(object));
+    }
+}
+
+#[test]
+fn big_requests_get_a_chunk_of_their_own() {
+    let mut gc = growing_gc(1 << 12, 1 << 12, 0);
+    let mut roots = root_slots(&mut gc, 1);
+    roots[0] = gc.malloc(1 << 16).expect("GC heap exhausted");
+    gc.cleanup();
+
+    assert!(alive(&gc, roots[0]));
+    assert_eq!(gc.object_base(unsafe { roots[0].add((1 << 16) - 1) }), Some(roots[0]));
+}
+
+#[test]
+fn growth_stops_at_the_chunk_limit() {
+    // every object is too big for the initial heap and needs an extra chunk
+    let mut gc = growing_gc(1 << 12, 1 << 12, 0);
+    let mut roots = root_slots(&mut gc, 1);
+    let count = (0..2 * MAX_CHUNKS).take_while(|_| push(&mut gc, &mut roots[0], 1 << 13)).count();
+    assert_eq!(count, MAX_CHUNKS - 1);
+    assert_eq!(walk(roots[0]).len(), count);
+}
+
+/// Fill most of the heap with live memory, clean up, then ask for more than is left.
+/// Tell whether that was served without another cleanup collecting a dead object.
+fn grows_ahead_of_need(min_free_percent: usize) -> bool {
+    let mut gc = growing_gc(1 << 12, 1 << 13, min_free_percent);
+    let mut roots = root_slots(&mut gc, 1);
+    roots[0] = gc.malloc_atomic(3 << 10).expect("GC heap exhausted");
+    gc.cleanup();
+
+    let dead = Hidden::new(gc.malloc(64).expect("GC heap exhausted"));
+    gc.malloc_atomic(6 << 10).expect("GC heap exhausted");
+    alive(&gc, dead.get())
+}
+
+#[test]
+fn cleanup_grows_when_little_is_free() {
+    assert!(grows_ahead_of_need(50));
+    assert!(!grows_ahead_of_need(0));
+}
@@ -0,0 +1,105 @@
+extern crate scgc;
+
+use scgc::{Gc, GcPtr};
+
+
+const NODES: usize = 10_000;
+
+struct Node {
+    value: usize,
+    next: Option<GcPtr<Node>>,
+}
+
+
+fn new_gc(side_marks: bool) -> Gc {
+    let mut gc = Gc::new(1 << 20);
+    gc.set_side_marks(side_marks).expect("no memory for the mark bitmap");
+    gc
+}
+
+/// Marking a long list has to be linear, the old fixpoint loop needed one
+/// pass over the whole record table per node.
+fn long_linked_list(side_marks: bool) {
+    let mut gc = new_gc(side_marks);
+
+    // the head of the list is the only root, the last slot only marks the end of the range
+    let mut roots: [Option<GcPtr<Node>>; 2] = [None, None];
+    gc.stack_begin(&roots[0]);
+    gc.stack_end(&roots[1]);
+
+    for value in 0..NODES {
+        let node = Node { value, next: roots[0] };
+        roots[0] = Some(gc.alloc(node).expect("GC heap exhausted"));
+    }
+    gc.cleanup();
+
+    // churn through the rest of the heap, reusing memory must not touch the list
+    for _ in 0..NODES * 4 {
+        let garbage = gc.malloc(64).expect("GC heap exhausted");
+        unsafe { std::ptr::write_bytes(garbage as *mut u8, 0xff, 64) };
+    }
+
+    let mut node = roots[0];
+    let mut expected = NODES;
+    while let Some(current) = node {
+        let current = unsafe { current.as_ref() };
+        expected -= 1;
+        assert_eq!(current.value, expected);
+        node = current.next;
+    }
+    assert_eq!(expected, 0);
+}
+
+/// One object pointing to more children than the mark stack holds,
+/// the ones which don't fit are found again by rescanning the record table
+/// and still get their own children marked.
+fn wide_object(side_marks: bool) {
+    const CHILDREN: usize = 4096;
+    type Children = [Option<GcPtr<Node>>; CHILDREN];
+    let mut gc = new_gc(side_marks);
+
+    let mut roots: [Option<GcPtr<Children>>; 2] = [None, None];
+    gc.stack_begin(&roots[0]);
+    gc.stack_end(&roots[1]);
+
+    roots[0] = Some(gc.alloc([None; CHILDREN]).expect("GC heap exhausted"));
+    for value in 0..CHILDREN {
+        let grandchild = gc.alloc(Node { value, next: None }).expect("GC heap exhausted");
+        let child = gc.alloc(Node { value, next: Some(grandchild) }).expect("GC heap exhausted");
+        unsafe { (*roots[0].unwrap().as_ptr())[value] = Some(child) };
+    }
+    gc.cleanup();
+
+    // node sized garbage, so freed nodes are the first to be reused
+    let size = std::mem::size_of::<Node>();
+    for _ in 0..CHILDREN * 8 {
+        let garbage = gc.malloc(size).expect("GC heap exhausted");
+        unsafe { std::ptr::write_bytes(garbage as *mut u8, 0xff, size) };
+    }
+
+    for (value, child) in unsafe { roots[0].unwrap().as_ref() }.iter().enumerate() {
+        let child = unsafe { child.unwrap().as_ref() };
+        assert_eq!(child.value, value);
+        assert_eq!(unsafe { child.next.unwrap().as_ref() }.value, value);
+    }
+}
+
+#[test]
+fn long_linked_list_survives_cleanup() {
+    long_linked_list(false);
+}
+
+#[test]
+fn long_linked_list_survives_cleanup_with_side_marks() {
+    long_linked_list(true);
+}
+
+#[test]
+fn wide_object_overflows_mark_stack() {
+    wide_object(false);
+}
+
+#[test]
+fn wide_object_overflows_mark_stack_with_side_marks() {
+    wide_object(true);
+}
@@ -0,0 +1,32 @@
+extern crate scgc;
+
+mod common;
+
+use common::{alive, gc_without_stack};
+use scgc::{Gc, GrowthPolicy};
+
+
+const HUGE: [usize; 4] = [usize::MAX, usize::MAX - 8, usize::MAX - 4096, usize::MAX / 2];
+
+/// requests that can't fit in the address space fail, and the heap stays usable
+fn rejects_huge_requests(gc: &mut Gc) {
+    for &size in &HUGE {
+        assert_eq!(gc.malloc(size), None);
+        assert_eq!(gc.malloc_aligned(size, 4096), None);
+    }
+    let addr = gc.malloc(64).expect("GC heap exhausted");
+    assert!(alive(gc, addr));
+}
+
+#[test]
+fn huge_requests_fail() {
+    let mut gc = gc_without_stack(1 << 16);
+    rejects_huge_requests(&mut gc);
+}
+
+#[test]
+fn huge_requests_fail_when_growing() {
+    let mut gc = gc_without_stack(1 << 16);
+    gc.set_growth_policy(Some(GrowthPolicy { chunk_size: 1 << 16, min_free_percent: 25 }));
+    rejects_huge_requests(&mut gc);
+}
@@ -0,0 +1,42 @@
+extern crate scgc;
+
+use scgc::{Gc, PointerPolicy};
+
+use std::ptr;
+
+
+/// Root a 64 bytes object only through a pointer `offset` bytes into it,
+/// and tell whether it survives a cleanup under `policy`
+fn survives(policy: PointerPolicy, offset: usize) -> bool {
+    let mut gc = Gc::new(1 << 16);
+    gc.set_pointer_policy(policy);
+    let mut roots = [ptr::null::<u8>(); 2];
+    gc.stack_begin(&roots[0]);
+    gc.stack_end(&roots[1]);
+
+    let addr = gc.malloc(64).expect("GC heap exhausted");
+    unsafe { ptr::write_bytes(addr as *mut u8, 0, 64) };
+    roots[0] = unsafe { addr.add(offset) };
+    gc.cleanup();
+    gc.object_base(roots[0]) == Some(addr)
+}
+
+#[test]
+fn base_only_needs_the_start() {
+    assert!(survives(PointerPolicy::BaseOnly, 0));
+    assert!(!survives(PointerPolicy::BaseOnly, 1));
+}
+
+#[test]
+fn window_covers_the_first_bytes() {
+    assert!(survives(PointerPolicy::Window(8), 0));
+    assert!(survives(PointerPolicy::Window(8), 7));
+    assert!(!survives(PointerPolicy::Window(8), 8));
+}
+
+#[test]
+fn interior_covers_the_whole_object() {
+    assert!(survives(PointerPolicy::Interior, 0));
+    assert!(survives(PointerPolicy::Interior, 63));
+    assert!(!survives(PointerPolicy::Interior, 64));
+}
@@ -0,0 +1,37 @@
+extern crate scgc;
+
+mod common;
+
+use common::{alive, gc_without_stack};
+use scgc::Root;
+
+#[test]
+fn rooted_objects_survive_cleanup() {
+    let mut gc = gc_without_stack(1 << 16);
+    let mut handles: Vec<Root<[usize; 8]>> = Vec::new();
+    for value in 0..16 {
+        let ptr = gc.alloc([value; 8]).expect("GC heap exhausted");
+        handles.push(gc.root(ptr));
+    }
+    // dropping some handles unlinks them from the middle of the list
+    let clones: Vec<_> = handles.iter().step_by(2).cloned().collect();
+    handles.retain(|handle| unsafe { handle.get().as_ref() }[0] % 2 == 1);
+    gc.cleanup();
+
+    for handle in handles.iter().chain(&clones) {
+        assert!(alive(&gc, handle.get().as_ptr() as *const u8));
+        let values = unsafe { handle.get().as_ref() };
+        assert!(values.iter().all(|&value| value == values[0]));
+    }
+}
+
+#[test]
+fn handles_can_outlive_the_gc() {
+    let mut gc = gc_without_stack(1 << 16);
+    let ptr = gc.alloc(42usize).expect("GC heap exhausted");
+    let first = gc.root(ptr);
+    let second = first.clone();
+    drop(gc);
+    drop(first);
+    drop(second);
+}
@@ -0,0 +1,58 @@
+extern crate scgc;
+
+mod common;
+
+use common::{alive, gc_without_stack, root_slots};
+
+use std::ptr;
+#[cfg(all(feature = "linux", target_os = "linux"))]
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+#[test]
+fn registered_range_keeps_objects_alive() {
+    let mut gc = gc_without_stack(1 << 16);
+    let mut roots = root_slots(&mut gc, 4);
+
+    roots[2] = gc.malloc(64).expect("GC heap exhausted");
+    gc.cleanup();
+    assert!(alive(&gc, roots[2]));
+}
+
+#[test]
+fn root_table_is_bounded() {
+    let mut gc = gc_without_stack(1 << 16);
+    let root = ptr::null::<u8>();
+    let addr = &root as *const *const u8 as *const u8;
+    let added = (0..64).take_while(|_| gc.add_roots(addr, addr).is_ok()).count();
+    assert!(added > 0 && added < 64);
+}
+
+#[test]
+fn removed_slots_are_reused() {
+    let mut gc = gc_without_stack(1 << 16);
+    let root = ptr::null::<u8>();
+    let addr = &root as *const *const u8 as *const u8;
+    let ids: Vec<_> = (0..64).map_while(|_| gc.add_roots(addr, addr).ok()).collect();
+    assert!(gc.add_roots(addr, addr).is_err());
+
+    assert!(gc.remove_roots(ids[3]));
+    assert!(!gc.remove_roots(ids[3]));
+    let id = gc.add_roots(addr, addr).expect("removed slot isn't reused");
+    // the old handle doesn't refer to the new range
+    assert!(!gc.remove_roots(ids[3]));
+    assert!(gc.remove_roots(id));
+}
+
+#[cfg(all(feature = "linux", target_os = "linux"))]
+static GLOBAL: AtomicPtr<u8> = AtomicPtr::new(ptr::null_mut());
+
+#[cfg(all(feature = "linux", target_os = "linux"))]
+#[test]
+fn data_segments_keep_globals_alive() {
+    let mut gc = gc_without_stack(1 << 16);
+    gc.set_scan_data_segments(true);
+
+    GLOBAL.store(gc.malloc(64).expect("GC heap exhausted") as *mut u8, Ordering::SeqCst);
+    gc.cleanup();
+    assert!(alive(&gc, GLOBAL.load(Ordering::SeqCst)));
+}
@@ -0,0 +1,44 @@
+extern crate scgc;
+
+mod common;
+
+use common::{alive, gc_without_stack, Hidden};
+
+use std::ptr;
+
+
+/// Store the only pointer to an object `offset` bytes into a root buffer,
+/// register the first `len` bytes of the buffer and tell whether the object survives a cleanup
+fn survives(unaligned: bool, offset: usize, len: usize) -> bool {
+    let mut gc = gc_without_stack(1 << 16);
+    gc.set_unaligned_scan(unaligned);
+    let mut buffer = Box::new([0usize; 4]);
+    let begin = buffer.as_mut_ptr() as *mut u8;
+    gc.add_roots(begin, unsafe { begin.add(len) }).expect("no room for roots");
+
+    let object = gc.malloc(64).expect("GC heap exhausted");
+    unsafe { ptr::write_unaligned(begin.add(offset) as *mut *const u8, object) };
+    let object = Hidden::new(object);
+    gc.cleanup();
+    alive(&gc, object.get())
+}
+
+#[test]
+fn aligned_words_are_scanned() {
+    assert!(survives(false, 8, 32));
+    assert!(survives(true, 8, 32));
+}
+
+#[test]
+fn unaligned_pointers_need_unaligned_scan() {
+    assert!(!survives(false, 9, 32));
+    assert!(survives(true, 9, 32));
+}
+
+#[test]
+fn words_straddling_the_end_are_skipped() {
+    assert!(!survives(false, 8, 15));
+    assert!(!survives(true, 8, 15));
+    assert!(survives(false, 8, 16));
+    assert!(survives(true, 8, 16));
+}
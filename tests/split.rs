@@ -0,0 +1,30 @@
+extern crate scgc;
+
+mod common;
+
+use common::{alive, gc_without_stack, root_slots, Hidden};
+
+
+#[test]
+fn split_tail_is_registered_and_reused() {
+    let mut gc = gc_without_stack(1 << 16);
+    let mut roots = root_slots(&mut gc, 1);
+
+    // a dead block the live object after it keeps off free memory
+    let dead = Hidden::new(gc.malloc(256).expect("GC heap exhausted"));
+    roots[0] = gc.malloc(64).expect("GC heap exhausted");
+    gc.cleanup();
+
+    let first = gc.malloc(64).expect("GC heap exhausted");
+    assert_eq!(first, dead.get());
+    // the tail is a deallocated record of its own, not part of the new object
+    let tail = unsafe { first.add(64) };
+    assert_eq!(gc.object_base(unsafe { tail.add(36) }), None);
+
+    assert_eq!(gc.malloc(64), Some(tail));
+    // records stay sorted, every lookup still lands on its own object
+    assert_eq!(gc.object_base(unsafe { first.add(63) }), Some(first));
+    assert_eq!(gc.object_base(unsafe { tail.add(63) }), Some(tail));
+    assert_eq!(gc.object_base(unsafe { tail.add(64) }), None);
+    assert!(alive(&gc, roots[0]));
+}
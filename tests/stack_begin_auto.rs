@@ -0,0 +1,19 @@
+#![cfg(all(feature = "linux", target_os = "linux"))]
+
+extern crate scgc;
+
+use scgc::Gc;
+
+use std::hint::black_box;
+
+
+#[test]
+fn auto_stack_begin_covers_the_caller() {
+    let mut gc = Gc::new(1 << 16);
+    gc.stack_begin_auto().expect("no stack bounds");
+
+    let root = [gc.malloc(64).expect("GC heap exhausted")];
+    black_box(&root);
+    gc.cleanup();
+    assert_eq!(gc.object_base(root[0]), Some(root[0]));
+}
@@ -0,0 +1,33 @@
+extern crate scgc;
+
+use scgc::Gc;
+
+use std::hint::black_box;
+
+
+/// allocate in a frame below the caller and clean up without touching the stack end
+#[inline(never)]
+fn survives_cleanup(gc: &mut Gc) -> bool {
+    let root = [gc.malloc(64).expect("GC heap exhausted")];
+    black_box(&root);
+    gc.cleanup();
+    gc.object_base(root[0]) == Some(root[0])
+}
+
+#[test]
+fn cleanup_captures_the_stack_top() {
+    let mut gc = Gc::new(1 << 16);
+    let bottom = 0usize;
+    gc.stack_begin(&bottom);
+    assert!(survives_cleanup(&mut gc));
+}
+
+#[test]
+fn cleared_stack_end_follows_the_stack_top_again() {
+    let mut gc = Gc::new(1 << 16);
+    let bottom = 0usize;
+    gc.stack_begin(&bottom);
+    gc.stack_end(&bottom);
+    gc.clear_stack_end();
+    assert!(survives_cleanup(&mut gc));
+}
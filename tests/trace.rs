@@ -0,0 +1,76 @@
+extern crate scgc;
+#[macro_use]
+extern crate scgc_derive;
+
+mod common;
+
+use common::{alive, gc_without_stack, root_slots, Hidden};
+use scgc::GcPtr;
+
+use std::ptr;
+
+
+#[derive(Trace)]
+struct Leaf(usize);
+
+/// `address` looks like a pointer, but it's only traced as an integer
+#[derive(Trace)]
+struct Parent {
+    child: Option<GcPtr<Leaf>>,
+    address: usize,
+}
+
+#[derive(Trace)]
+#[allow(dead_code)]
+enum Tree<T> {
+    Empty,
+    Leaf(T),
+    Node { left: GcPtr<Tree<T>>, right: GcPtr<Tree<T>> },
+}
+
+#[test]
+fn only_traced_fields_keep_objects_alive() {
+    let mut gc = gc_without_stack(1 << 16);
+    let mut roots = root_slots(&mut gc, 1);
+
+    let child = gc.alloc(Leaf(1)).expect("GC heap exhausted");
+    let other = gc.alloc(Leaf(2)).expect("GC heap exhausted");
+    let parent = Parent { child: Some(child), address: other.as_ptr() as usize };
+    roots[0] = gc.alloc_traced(parent).expect("GC heap exhausted").as_ptr() as *const u8;
+    let (child, other) = (Hidden::new(child.as_ptr() as *const u8), Hidden::new(other.as_ptr() as *const u8));
+    gc.cleanup();
+
+    assert!(alive(&gc, child.get()));
+    assert_eq!(gc.object_base(other.get()), None);
+}
+
+#[test]
+fn traced_tree_survives_cleanup() {
+    let mut gc = gc_without_stack(1 << 16);
+    let mut roots = root_slots(&mut gc, 1);
+
+    let mut tree = gc.alloc_traced(Tree::Empty).expect("GC heap exhausted");
+    for value in 0..100usize {
+        let leaf = gc.alloc_traced(Tree::Leaf(value)).expect("GC heap exhausted");
+        tree = gc.alloc_traced(Tree::Node { left: leaf, right: tree }).expect("GC heap exhausted");
+    }
+    roots[0] = tree.as_ptr() as *const u8;
+    gc.cleanup();
+
+    // churn through the heap, reusing memory must not touch the tree
+    for _ in 0..1000 {
+        let garbage = gc.malloc(32).expect("GC heap exhausted");
+        unsafe { ptr::write_bytes(garbage as *mut u8, 0xff, 32) };
+    }
+
+    let mut expected = 100;
+    while let Tree::Node { left, right } = *unsafe { tree.as_ref() } {
+        expected -= 1;
+        match *unsafe { left.as_ref() } {
+            Tree::Leaf(value) => assert_eq!(value, expected),
+            _ => panic!("left branch isn't a leaf"),
+        }
+        tree = right;
+    }
+    assert_eq!(expected, 0);
+}
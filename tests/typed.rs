@@ -0,0 +1,45 @@
+extern crate scgc;
+
+mod common;
+
+use common::{alive, gc_without_stack, root_slots, Hidden};
+use scgc::Descriptor;
+
+use std::mem;
+use std::ptr;
+
+
+const WORDS: usize = 4;
+
+/// Allocate a typed parent of `WORDS` words, each one pointing to a child of its own,
+/// root the parent outside the stack and tell which children survive a cleanup
+fn surviving_children(descriptor: Descriptor) -> [bool; WORDS] {
+    let mut gc = gc_without_stack(1 << 16);
+    let mut roots = root_slots(&mut gc, 1);
+
+    roots[0] = gc.malloc_typed(WORDS * mem::size_of::<usize>(), descriptor).expect("GC heap exhausted");
+    let parent = roots[0] as *mut *const u8;
+    let children: Vec<Hidden> = (0..WORDS).map(|index| {
+        let child = gc.malloc(64).expect("GC heap exhausted");
+        unsafe { ptr::write_bytes(child as *mut u8, 0, 64) };
+        unsafe { ptr::write(parent.add(index), child) };
+        Hidden::new(child)
+    }).collect();
+    gc.cleanup();
+
+    let mut survivors = [false; WORDS];
+    for (survivor, child) in survivors.iter_mut().zip(&children) {
+        *survivor = alive(&gc, child.get());
+    }
+    survivors
+}
+
+#[test]
+fn only_pointer_words_are_scanned() {
+    assert_eq!(surviving_children(Descriptor::new(0b1001, 4)), [true, false, false, true]);
+}
+
+#[test]
+fn descriptor_repeats_over_longer_objects() {
+    assert_eq!(surviving_children(Descriptor::new(0b10, 2)), [false, true, false, true]);
+}